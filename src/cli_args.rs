@@ -1,20 +1,157 @@
 use std::path::PathBuf;
 
-use crate::util::default_migrations_dir;
-use clap::{Parser, Subcommand};
+use clap::{Args, Parser, Subcommand};
+use migren::{IdFormat, lock::DEFAULT_LOCK_TIMEOUT, util::default_migrations_dir};
 
 #[derive(Subcommand, Debug)]
 pub enum Command {
     /// Move to selected migration (can be used as rollback as well)
-    To { migration_id: u32 },
+    To {
+        migration_id: u64,
+        #[command(flatten)]
+        migrate: MigrateArgs,
+    },
     /// Move to last added migration
-    Top,
+    Top {
+        #[command(flatten)]
+        migrate: MigrateArgs,
+    },
+    /// Apply next migrations. Moves to last migration if number of steps is not set
+    Up {
+        steps: Option<u64>,
+        #[command(flatten)]
+        migrate: MigrateArgs,
+    },
+    /// Revert last applied migrations
+    Down {
+        #[arg(default_value_t = 1)]
+        steps: u64,
+        #[command(flatten)]
+        migrate: MigrateArgs,
+    },
+    /// Mark existing database as being at migration without running any files
+    Baseline {
+        migration_id: u64,
+        /// Do not ask for confirmation
+        #[arg(short, long)]
+        yes: bool,
+        /// Seconds to wait for migration lock held by another migren
+        #[arg(long, default_value_t = DEFAULT_LOCK_TIMEOUT.as_secs())]
+        lock_timeout: u64,
+    },
+    /// Apply new and changed files of seed set from seeds/<set> directory
+    Seed {
+        /// Seed set, subdirectory of seeds directory
+        #[arg(default_value = "base")]
+        set: String,
+        /// Show seed files which would be applied, without executing them
+        #[arg(long)]
+        dry_run: bool,
+        /// Seconds to wait for migration lock held by another migren
+        #[arg(long, default_value_t = DEFAULT_LOCK_TIMEOUT.as_secs())]
+        lock_timeout: u64,
+    },
+    /// Revert last applied migrations and apply them back
+    Redo {
+        /// How many migrations to redo
+        #[arg(long, default_value_t = 1)]
+        steps: u64,
+        /// Revert migrations once more and fail if down files do not restore schema
+        #[arg(long)]
+        check_schema: bool,
+        #[command(flatten)]
+        migrate: MigrateArgs,
+    },
     /// Status about DB and migrations
     Status,
+    /// History of applied and reverted migrations
+    History,
+    /// Save checksums of current migration files as applied ones
+    AcceptChecksums {
+        /// Seconds to wait for migration lock held by another migren
+        #[arg(long, default_value_t = DEFAULT_LOCK_TIMEOUT.as_secs())]
+        lock_timeout: u64,
+    },
+    /// Remove migration lock left by migren killed while migrating SQLite database
+    Unlock,
     /// Execute .sql file for db
     Exec { sql_file: PathBuf },
     /// Create new migration
-    New { name: String },
+    New {
+        name: String,
+        /// Sql file with desired schema. Up and down files are generated from difference
+        /// between schema of current migrations and this one
+        #[arg(long)]
+        from_schema: Option<PathBuf>,
+        /// Empty database to compare schemas in. Temporary SQLite database is used if not set
+        #[arg(long, requires = "from_schema")]
+        shadow_url: Option<String>,
+    },
+    /// Write down file of migration reverting statements of its up file
+    GenDown {
+        migration_id: u64,
+        /// Replace statements of down file without confirmation
+        #[arg(short, long)]
+        yes: bool,
+    },
+    /// Rebuild .migren.json from migration files in directory
+    Rebuild {
+        /// Write new .migren.json without confirmation
+        #[arg(short, long)]
+        yes: bool,
+    },
+    /// Apply, revert and apply again every migration on scratch database, checking schema
+    Test {
+        /// Scratch database url. Temporary SQLite database is used if not set
+        #[arg(long)]
+        scratch_url: Option<String>,
+    },
+    /// Check .migren.json links and migration files without connecting to database
+    Verify,
+    /// Write database schema into schema.sql next to .migren.json
+    Schema {
+        /// Do not write anything, fail if schema.sql does not match database schema
+        #[arg(long)]
+        check: bool,
+    },
+    /// Change how ids of new migrations are generated
+    SetIdFormat { id_format: IdFormat },
+    /// Squash range of migrations into single migration with id of the last one
+    Squash {
+        from: u64,
+        to: u64,
+        #[arg(long, default_value = "squashed")]
+        name: String,
+    },
+    /// Create merge migration after every diverged head
+    Merge {
+        #[arg(default_value = "merge")]
+        name: String,
+    },
+}
+
+/// Arguments for commands which move database between migrations
+#[derive(Args, Debug)]
+pub struct MigrateArgs {
+    /// Apply migrations even if already applied files were changed on disk
+    #[arg(long)]
+    pub allow_drift: bool,
+    /// Show migrations and statements which would be applied, without executing them
+    #[arg(long)]
+    pub dry_run: bool,
+    /// Seconds to wait for migration lock held by another migren
+    #[arg(long, default_value_t = DEFAULT_LOCK_TIMEOUT.as_secs())]
+    pub lock_timeout: u64,
+}
+
+/// Format of command results
+#[derive(clap::ValueEnum, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// Human-readable log lines
+    #[default]
+    Text,
+    /// JSON printed to stdout. Errors are printed as JSON objects as well
+    Json,
 }
 
 #[derive(Parser, Debug)]
@@ -22,6 +159,14 @@ pub enum Command {
 pub struct CliArgs {
     #[arg(short, long, default_value = default_migrations_dir().into_os_string())]
     pub directory: PathBuf,
+    #[arg(long, global = true, value_enum, default_value_t = OutputFormat::Text)]
+    pub output: OutputFormat,
+    /// Show only warnings and errors
+    #[arg(short, long, global = true, conflicts_with = "verbose")]
+    pub quiet: bool,
+    /// Show debug output. Repeat for more
+    #[arg(short, long, global = true, action = clap::ArgAction::Count)]
+    pub verbose: u8,
     #[command(subcommand)]
     pub command: Command,
 }
@@ -1,82 +1,708 @@
 use std::fs;
-use std::path::PathBuf;
+use std::io::{self, Write};
+use std::path::Path;
 
-use log::info;
+use log::{debug, error, info, warn};
+use migren::errors::Result;
+use migren::history::Direction;
+use migren::roundtrip::MigrationTestFailure;
+use migren::schema::schema_file;
+use migren::util::{DiffLine, MIGRATIONS_FILE_NAME, diff_lines, save_migrations_data};
+use migren::{IdFormat, MigrationReport, Migrator, MigrenError};
+use serde_json::{Value, json};
 
-use crate::cli_args::CliArgs;
-use crate::database::connect;
-use crate::env_args::EnvArgs;
-use crate::errors::Result;
-use crate::features::DatabaseMigrationer;
-use crate::util::{MIGRATIONS_FILE_NAME, load_migrations_data};
+use crate::cli_args::OutputFormat;
 
-pub fn new(cli: &CliArgs, env: &EnvArgs, name: &str) -> Result<()> {
-    let migration_data_file_path = PathBuf::from(MIGRATIONS_FILE_NAME);
+/// Asks user to confirm action in terminal. Question goes to stderr, so it does not mix
+/// with JSON output
+fn confirm(question: &str) -> Result<bool> {
+    eprint!("{question} [y/N] ");
+    io::stderr().flush()?;
 
-    let mut migration_data = load_migrations_data(&migration_data_file_path)?;
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer)?;
+    Ok(matches!(answer.trim(), "y" | "Y" | "yes"))
+}
+
+fn print_json(value: &Value) -> Result<()> {
+    println!("{}", serde_json::to_string_pretty(value)?);
+    Ok(())
+}
+
+/// Plan of migration and result of every migration file in it
+fn report_json(report: &MigrationReport) -> Value {
+    let migrations: Vec<Value> = report
+        .applied
+        .iter()
+        .map(|migration| {
+            let result = match (report.dry_run, migration.direction) {
+                (true, _) => "planned",
+                (false, Direction::Up) => "applied",
+                (false, Direction::Down) => "reverted",
+            };
+            json!({
+                "id": migration.id,
+                "direction": migration.direction.as_str(),
+                "file": migration.file,
+                "result": result,
+            })
+        })
+        .collect();
+    let repeatables: Vec<Value> = report
+        .repeatables
+        .iter()
+        .map(|repeatable| {
+            json!({
+                "name": repeatable.name,
+                "file": repeatable.file,
+                "result": if report.dry_run { "planned" } else { "applied" },
+            })
+        })
+        .collect();
+
+    json!({
+        "from": report.from,
+        "to": report.to,
+        "dry_run": report.dry_run,
+        "migrations": migrations,
+        "repeatables": repeatables,
+    })
+}
+
+fn log_report(report: &MigrationReport, output: OutputFormat) -> Result<()> {
+    if output == OutputFormat::Json {
+        return print_json(&report_json(report));
+    }
+
+    if report.applied.is_empty() && report.repeatables.is_empty() {
+        info!("Database is already at migration {}", report.to);
+        return Ok(());
+    }
+
+    if report.dry_run {
+        info!(
+            "Dry run. Database is at migration {}, moving to {} would apply:",
+            report.from, report.to
+        );
+        for migration in &report.applied {
+            if migration.is_transactional()? {
+                info!("Migration {} - file {:?}", migration.id, migration.file);
+            } else {
+                info!(
+                    "Migration {} - file {:?} (outside of transaction)",
+                    migration.id, migration.file
+                );
+            }
+            for (index, statement) in migration.statements(report.backend)?.iter().enumerate() {
+                info!(
+                    "Statement {} (line {}):\n{}",
+                    index + 1,
+                    statement.line,
+                    statement.sql
+                );
+            }
+        }
+        for repeatable in &report.repeatables {
+            info!("Changed repeatable file {:?}", repeatable.file);
+        }
+        return Ok(());
+    }
+
+    for migration in &report.applied {
+        match migration.direction {
+            Direction::Up => info!("Applied file {:?}", migration.file),
+            Direction::Down => info!("Reverted with file {:?}", migration.file),
+        }
+    }
+    for repeatable in &report.repeatables {
+        info!("Applied repeatable file {:?}", repeatable.file);
+    }
+    match report.applied.is_empty() {
+        true => info!("Database is already at migration {}", report.to),
+        false => info!(
+            "Moved database from migration {} to {}",
+            report.from, report.to
+        ),
+    }
+    Ok(())
+}
 
+pub fn new(migrator: &Migrator, name: &str, output: OutputFormat) -> Result<()> {
     info!("Creating new migration {name}");
-    migration_data.new_migration(name)?;
+    let migration = migrator.new_migration(name)?;
+    if output == OutputFormat::Json {
+        return print_json(&json!(migration));
+    }
+
+    info!("Created migration {}: {:?}", migration.id, migration.files);
+    Ok(())
+}
+
+pub async fn new_from_schema(
+    migrator: &Migrator,
+    name: &str,
+    target_schema: &Path,
+    shadow_url: Option<&str>,
+    output: OutputFormat,
+) -> Result<()> {
+    let Some(generated) = migrator
+        .generate_migration(name, target_schema, shadow_url)
+        .await?
+    else {
+        if output == OutputFormat::Json {
+            return print_json(&Value::Null);
+        }
+        info!("Schema of migrations already matches {target_schema:?}. Migration was not created");
+        return Ok(());
+    };
+
+    if output == OutputFormat::Json {
+        return print_json(&json!({
+            "migration": generated.migration,
+            "unsupported": generated.unsupported,
+        }));
+    }
+
+    info!(
+        "Created migration {} from schema {target_schema:?}: {:?}",
+        generated.migration.id, generated.migration.files
+    );
+    for change in &generated.unsupported {
+        warn!("Can not generate statement, write it manually (marked with TODO): {change}");
+    }
+    Ok(())
+}
+
+pub fn gen_down(
+    migrator: &Migrator,
+    migration_id: u64,
+    yes: bool,
+    output: OutputFormat,
+) -> Result<()> {
+    let generated = migrator.generate_down(migration_id)?;
+    let gen_down_json = |written: bool| {
+        json!({
+            "file": generated.file,
+            "written": written,
+            "unsupported": generated.unsupported,
+        })
+    };
+
+    if generated.overwrites && !yes {
+        info!("New content of {:?}:\n{}", generated.file, generated.sql);
+        let question = format!(
+            "Down file {:?} already has statements. Replace them?",
+            generated.file
+        );
+        if !confirm(&question)? {
+            if output == OutputFormat::Json {
+                return print_json(&gen_down_json(false));
+            }
+            info!("Down file was not changed");
+            return Ok(());
+        }
+    }
+
+    fs::write(&generated.file, &generated.sql)?;
+    if output == OutputFormat::Json {
+        return print_json(&gen_down_json(true));
+    }
+    info!("Wrote down file {:?}", generated.file);
+    for statement in &generated.unsupported {
+        warn!("Write it manually (marked with TODO): {statement}");
+    }
+    Ok(())
+}
 
-    let json_migrations_data = serde_json::to_string(&migration_data)?;
+pub fn merge(migrator: &Migrator, name: &str, output: OutputFormat) -> Result<()> {
+    let migration = migrator.merge(name)?;
+    if output == OutputFormat::Json {
+        return print_json(&json!(migration));
+    }
 
-    fs::write(&migration_data_file_path, json_migrations_data)?;
-    info!("Saved migrations data to {migration_data_file_path:?}");
+    info!(
+        "Created merge migration {} after migrations {:?}: {:?}",
+        migration.id, migration.prev_migration_ids, migration.files
+    );
     Ok(())
 }
 
-pub async fn to(cli: &CliArgs, env: &EnvArgs, migration_id: u32) -> Result<()> {
-    let mut db_connection = connect(&env.database_url).await?;
+pub fn squash(
+    migrator: &Migrator,
+    from: u64,
+    to: u64,
+    name: &str,
+    output: OutputFormat,
+) -> Result<()> {
+    let migration = migrator.squash(from, to, name)?;
+    if output == OutputFormat::Json {
+        return print_json(&json!(migration));
+    }
 
-    let migration_data_file_path = PathBuf::from(MIGRATIONS_FILE_NAME);
-    let migrations_data = load_migrations_data(&migration_data_file_path)?;
+    info!(
+        "Squashed migrations from {from} to {to} into migration {}: {:?}",
+        migration.id, migration.files
+    );
+    Ok(())
+}
+
+pub fn rebuild(migrator: &Migrator, yes: bool, output: OutputFormat) -> Result<()> {
+    let rebuilt = migrator.rebuild()?;
+    let rebuild_json = |changed: bool, written: bool| {
+        json!({
+            "file": migrator.directory().join(MIGRATIONS_FILE_NAME),
+            "changed": changed,
+            "written": written,
+            "unpaired": rebuilt.unpaired,
+            "orphaned": rebuilt.orphaned,
+        })
+    };
+
+    for file in &rebuilt.unpaired {
+        warn!("Unpaired migration file: {file:?}");
+    }
+    for file in &rebuilt.orphaned {
+        warn!("Orphaned sql file: {file:?}");
+    }
 
-    db_connection.to(migrations_data, migration_id).await?;
+    let new = serde_json::to_string_pretty(&rebuilt.rebuilt)?;
+    let diff = diff_lines(rebuilt.current.as_deref().unwrap_or_default(), &new);
+    if diff.iter().all(|line| matches!(line, DiffLine::Same(_))) {
+        if output == OutputFormat::Json {
+            return print_json(&rebuild_json(false, false));
+        }
+        info!("Migrations data is up to date");
+        return Ok(());
+    }
 
+    info!("Changes in migrations data:");
+    for line in diff {
+        match line {
+            DiffLine::Same(line) => info!("  {line}"),
+            DiffLine::Removed(line) => info!("- {line}"),
+            DiffLine::Added(line) => info!("+ {line}"),
+        }
+    }
+
+    if !yes && !confirm("Write rebuilt migrations data?")? {
+        if output == OutputFormat::Json {
+            return print_json(&rebuild_json(true, false));
+        }
+        info!("Migrations data was not changed");
+        return Ok(());
+    }
+
+    let path = save_migrations_data(&rebuilt.rebuilt)?;
+    if output == OutputFormat::Json {
+        return print_json(&rebuild_json(true, true));
+    }
+    info!("Saved migrations data to {path:?}");
     Ok(())
 }
 
-pub async fn top(cli: &CliArgs, env: &EnvArgs) -> Result<()> {
-    let mut db_connection = connect(&env.database_url).await?;
+pub async fn test(
+    migrator: &Migrator,
+    scratch_url: Option<&str>,
+    output: OutputFormat,
+) -> Result<()> {
+    let tests = migrator.test(scratch_url).await?;
+    if output == OutputFormat::Json {
+        print_json(&json!(tests))?;
+    } else {
+        for test in &tests {
+            let Some(failure) = &test.failure else {
+                info!("ok     {:>14} {}", test.id, test.name);
+                continue;
+            };
+
+            error!("Migration {} {} failed round-trip test", test.id, test.name);
+            let diff = match failure {
+                MigrationTestFailure::FileFailed { direction, error } => {
+                    error!("{} file failed: {error}", direction.as_str());
+                    continue;
+                }
+                MigrationTestFailure::LossyDown(diff) => {
+                    error!(
+                        "Down file did not restore schema. Schema after down compared to schema before up:"
+                    );
+                    diff
+                }
+                MigrationTestFailure::ReapplyDiffers(diff) => {
+                    error!("Up file applied again gave different schema:");
+                    diff
+                }
+            };
+            for line in diff {
+                match line {
+                    DiffLine::Removed(line) => error!("- {line}"),
+                    DiffLine::Added(line) => error!("+ {line}"),
+                    DiffLine::Same(line) => error!("  {line}"),
+                }
+            }
+        }
+    }
+
+    match tests.last() {
+        Some(test) if test.failure.is_some() => Err(MigrenError::TestFailed(test.id)),
+        _ => {
+            info!("Every migration passed round-trip test");
+            Ok(())
+        }
+    }
+}
+
+pub fn verify(migrator: &Migrator, output: OutputFormat) -> Result<()> {
+    let issues = migrator.verify()?;
+    if output == OutputFormat::Json && issues.is_empty() {
+        return print_json(&json!({ "issues": [] }));
+    }
+    if issues.is_empty() {
+        info!("Migrations are valid");
+        return Ok(());
+    }
 
-    let migration_data_file_path = PathBuf::from(MIGRATIONS_FILE_NAME);
-    let migrations_data = load_migrations_data(&migration_data_file_path)?;
-    let to_migration = migrations_data.migrations_counter;
+    // Json output shows issues as part of error
+    if output == OutputFormat::Text {
+        for issue in &issues {
+            error!("{issue}");
+        }
+    }
+    Err(MigrenError::VerificationFailed(issues))
+}
 
-    db_connection.to(migrations_data, to_migration).await?;
+pub async fn schema(migrator: &mut Migrator, check: bool, output: OutputFormat) -> Result<()> {
+    if !check {
+        let path = migrator.write_schema().await?;
+        if output == OutputFormat::Json {
+            return print_json(&json!({ "file": path, "written": true }));
+        }
+        info!("Saved database schema to {path:?}");
+        return Ok(());
+    }
+
+    let diff = migrator.schema_diff().await?;
+    if diff.is_empty() {
+        if output == OutputFormat::Json {
+            let path = schema_file(migrator.directory());
+            return print_json(&json!({ "file": path, "written": false }));
+        }
+        info!("Schema file is up to date");
+        return Ok(());
+    }
+
+    info!("Schema file differs from database schema:");
+    for line in diff {
+        match line {
+            DiffLine::Removed(line) => info!("- {line}"),
+            DiffLine::Added(line) => info!("+ {line}"),
+            DiffLine::Same(line) => info!("  {line}"),
+        }
+    }
+    Err(MigrenError::SchemaStale(schema_file(migrator.directory())))
+}
+
+pub async fn set_id_format(
+    migrator: &mut Migrator,
+    id_format: IdFormat,
+    output: OutputFormat,
+) -> Result<()> {
+    migrator.set_id_format(id_format)?;
+
+    // Database is optional for this command, so it is checked only when configured
+    let integer_ids = match id_format {
+        IdFormat::Timestamp => match migrator.has_integer_migration_ids().await {
+            Ok(integer_ids) => integer_ids,
+            Err(MigrenError::DatabaseNotConfigured) => false,
+            Err(err) => {
+                warn!("Could not check type of migration id column: {err}");
+                false
+            }
+        },
+        IdFormat::Counter => false,
+    };
+    if integer_ids {
+        warn!(
+            "Database keeps migration ids in INTEGER column, too small for timestamp ids. Change migren_data.last_migration_applied to BIGINT before applying new migrations"
+        );
+    }
+
+    if output == OutputFormat::Json {
+        return print_json(
+            &json!({ "id_format": id_format, "integer_migration_ids": integer_ids }),
+        );
+    }
+    info!("New migrations will use {id_format:?} ids");
 
     Ok(())
 }
 
-pub async fn status(cli: &CliArgs, env: &EnvArgs) -> Result<()> {
-    let mut db_connection = connect(&env.database_url).await?;
+/// Keeps `schema.sql` in sync with database after migration. Migration is already committed,
+/// so failure is only warned about and `schema` command can write file later
+async fn write_schema(migrator: &mut Migrator, dry_run: bool) {
+    if dry_run {
+        return;
+    }
 
-    let migration_data_file_path = PathBuf::from(MIGRATIONS_FILE_NAME);
-    let migrations_data = load_migrations_data(&migration_data_file_path)?;
-    let migren_data = db_connection.migren_data().await?;
+    match migrator.write_schema().await {
+        Ok(path) => debug!("Saved database schema to {path:?}"),
+        Err(err) => warn!("Could not save database schema, run `migren schema` to retry: {err}"),
+    }
+}
 
-    info!("Migrations info:");
-    info!(
-        "Migrations counter is: {}",
-        migrations_data.migrations_counter
+pub async fn to(migrator: &mut Migrator, migration_id: u64, output: OutputFormat) -> Result<()> {
+    let report = migrator.to(migration_id).await?;
+    log_report(&report, output)?;
+    write_schema(migrator, report.dry_run).await;
+
+    Ok(())
+}
+
+pub async fn top(migrator: &mut Migrator, output: OutputFormat) -> Result<()> {
+    let report = migrator.top().await?;
+    log_report(&report, output)?;
+    write_schema(migrator, report.dry_run).await;
+
+    Ok(())
+}
+
+pub async fn up(migrator: &mut Migrator, steps: Option<u64>, output: OutputFormat) -> Result<()> {
+    let report = migrator.up(steps).await?;
+    log_report(&report, output)?;
+    write_schema(migrator, report.dry_run).await;
+
+    Ok(())
+}
+
+pub async fn down(migrator: &mut Migrator, steps: u64, output: OutputFormat) -> Result<()> {
+    let report = migrator.down(steps).await?;
+    log_report(&report, output)?;
+    write_schema(migrator, report.dry_run).await;
+
+    Ok(())
+}
+
+pub async fn baseline(
+    migrator: &mut Migrator,
+    migration_id: u64,
+    yes: bool,
+    output: OutputFormat,
+) -> Result<()> {
+    let question = format!(
+        "Mark database as being at migration {migration_id} without running any migration files?"
     );
-    info!("Migren version: {}", migrations_data.migren_version);
+    if !yes && !confirm(&question)? {
+        if output == OutputFormat::Json {
+            return print_json(&json!({ "migration_id": migration_id, "applied": false }));
+        }
+        info!("Database was not changed");
+        return Ok(());
+    }
+
+    migrator.baseline(migration_id).await?;
+    write_schema(migrator, false).await;
+    if output == OutputFormat::Json {
+        return print_json(&json!({ "migration_id": migration_id, "applied": true }));
+    }
+    info!("Database is marked as being at migration {migration_id}");
+
+    Ok(())
+}
+
+pub async fn redo(
+    migrator: &mut Migrator,
+    steps: u64,
+    check_schema: bool,
+    output: OutputFormat,
+) -> Result<()> {
+    let report = migrator.redo(steps, check_schema).await?;
+    if output == OutputFormat::Json {
+        print_json(&json!({
+            "down": report_json(&report.down),
+            "up": report_json(&report.up),
+        }))?;
+    } else {
+        log_report(&report.down, output)?;
+        log_report(&report.up, output)?;
+    }
+    write_schema(migrator, report.up.dry_run).await;
+
+    Ok(())
+}
+
+pub async fn seed(migrator: &mut Migrator, set: &str, output: OutputFormat) -> Result<()> {
+    let report = migrator.seed(set).await?;
+    if output == OutputFormat::Json {
+        return print_json(&json!(report));
+    }
+
+    if report.applied.is_empty() {
+        info!("Seed set {set} is already applied");
+        return Ok(());
+    }
+
+    for seed in &report.applied {
+        match (report.dry_run, seed.changed) {
+            (true, false) => info!("Would apply seed file {:?}", seed.file),
+            (true, true) => info!("Would apply changed seed file {:?} again", seed.file),
+            (false, false) => info!("Applied seed file {:?}", seed.file),
+            (false, true) => info!("Applied changed seed file {:?} again", seed.file),
+        }
+    }
+    if !report.dry_run {
+        info!(
+            "Applied {} files of seed set {set}, {} were applied already",
+            report.applied.len(),
+            report.unchanged.len()
+        );
+    }
+    Ok(())
+}
+
+pub async fn status(migrator: &mut Migrator, output: OutputFormat) -> Result<()> {
+    let status = migrator.status().await?;
+    if output == OutputFormat::Json {
+        return print_json(&json!(status));
+    }
+
+    info!("Migrations info:");
+    info!("Migrations counter is: {}", status.migrations_counter);
+    info!("Migren version: {}", status.migren_version);
 
     info!("Database info:");
+    if !status.initialized {
+        info!("Database is not initialized. Migren tables will be created by the first migration");
+    }
     info!(
-        "Database is at migration: {} - info about migration: {:#?}",
-        migren_data.last_migration_applied,
-        migrations_data.migration_by_id(migren_data.last_migration_applied as u32),
+        "Database is at migration: {}",
+        status.last_migration_applied
     );
-    info!("Migren version: {}", migren_data.migren_version);
+    if status.initialized {
+        info!("Migren version: {}", status.database.migren_version);
+    }
+
+    info!("Migrations:");
+    info!("   {:>14}  {:<8} NAME", "ID", "STATE");
+    for migration in &status.migrations {
+        info!(
+            "{} {:>14}  {:<8} {}",
+            if migration.current { "->" } else { "  " },
+            migration.id,
+            if migration.applied {
+                "applied"
+            } else {
+                "pending"
+            },
+            migration.name
+        );
+        for file in &migration.missing_files {
+            warn!("Migration {} file {file:?} does not exist", migration.id);
+        }
+    }
+
+    if status.database_ahead {
+        warn!(
+            "Database is at migration {}, which does not exist in .migren.json. Database was probably migrated from newer version of migrations",
+            status.last_migration_applied
+        );
+    }
+
+    if let Some(in_progress) = &status.in_progress {
+        warn!(
+            "Non-transactional migration {} ({}) started at {} failed halfway! Database may be partially migrated. Fix it manually and run migren again",
+            in_progress.migration_id, in_progress.direction, in_progress.started_at
+        );
+    }
+
+    if let Some(squashed_into) = status.squashed_into {
+        warn!(
+            "Database is at migration {}, which was squashed into migration {squashed_into}. Move it manually",
+            status.last_migration_applied
+        );
+    }
+
+    if status.heads.len() > 1 {
+        warn!(
+            "Migrations diverged into several heads: {:?}. Run `migren merge` to merge them",
+            status.heads
+        );
+    }
+
+    if status.drift.is_empty() {
+        info!("Applied migration files match saved checksums");
+    } else {
+        for drift in &status.drift {
+            warn!(
+                "Migration {} was changed after it was applied. File: {:?}, applied checksum: {}, current checksum: {:?}",
+                drift.id, drift.file, drift.applied_checksum, drift.current_checksum
+            );
+        }
+    }
+
+    Ok(())
+}
+
+pub async fn history(migrator: &mut Migrator, output: OutputFormat) -> Result<()> {
+    let history = migrator.history().await?;
+    if output == OutputFormat::Json {
+        return print_json(&json!(history));
+    }
+
+    if history.is_empty() {
+        info!("History is empty");
+        return Ok(());
+    }
+
+    for entry in &history {
+        info!(
+            "{:<8} {:>5} {} - started {}, finished {} ({} ms) by {}@{}, migren {}, checksum {}",
+            entry.direction,
+            entry.migration_id,
+            entry.name,
+            entry.started_at,
+            entry.finished_at,
+            entry.duration_ms,
+            entry.os_user,
+            entry.hostname,
+            entry.migren_version,
+            entry.checksum,
+        );
+    }
+
+    Ok(())
+}
+
+pub async fn accept_checksums(migrator: &mut Migrator, output: OutputFormat) -> Result<()> {
+    let accepted = migrator.accept_checksums().await?;
+    if output == OutputFormat::Json {
+        return print_json(&json!({ "accepted": accepted }));
+    }
+    info!("Saved checksums of current migration files");
+
+    Ok(())
+}
+
+pub async fn unlock(migrator: &mut Migrator, output: OutputFormat) -> Result<()> {
+    let holder = migrator.force_unlock().await?;
+    if output == OutputFormat::Json {
+        return print_json(&json!({ "removed_holder": holder }));
+    }
 
+    match holder {
+        Some(holder) => info!("Removed migration lock held by {holder}"),
+        None => info!("Migration lock is not held"),
+    }
     Ok(())
 }
 
-pub async fn exec(cli: &CliArgs, env: &EnvArgs, sql_file: &PathBuf) -> Result<()> {
-    let mut db_connection = connect(&env.database_url).await?;
-    let sql_query = fs::read_to_string(sql_file)?;
-    let res = db_connection.exec(&sql_query).await?;
+pub async fn exec(migrator: &mut Migrator, sql_file: &Path, output: OutputFormat) -> Result<()> {
+    let sql_query = fs::read_to_string(migrator.directory().join(sql_file))?;
+    let res = migrator.exec(&sql_query).await?;
+    if output == OutputFormat::Json {
+        return print_json(&json!({
+            "rows_affected": res.rows_affected(),
+            "last_insert_id": res.last_insert_id(),
+        }));
+    }
 
     info!("Execution result: {:#?}", res);
 
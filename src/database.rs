@@ -1,22 +1,271 @@
-use log::info;
+use std::{fs, path::PathBuf};
+
+use chrono::Utc;
+use log::debug;
 use sqlx::{AnyConnection, Connection};
 
-use crate::errors::Result;
+use crate::{errors::Result, history::timestamp, schema::Backend};
 
 const TABLE_CREATE: &str = "
 CREATE TABLE IF NOT EXISTS migren_data (
     migren_version TEXT,
-    last_migration_applied INTEGER
+    last_migration_applied BIGINT
+);
+";
+
+const CHECKSUMS_TABLE_CREATE: &str = "
+CREATE TABLE IF NOT EXISTS migren_checksums (
+    migration_id BIGINT,
+    checksum TEXT
+);
+";
+
+const IN_PROGRESS_TABLE_CREATE: &str = "
+CREATE TABLE IF NOT EXISTS migren_in_progress (
+    migration_id BIGINT,
+    direction TEXT,
+    started_at TEXT
+);
+";
+
+const HISTORY_TABLE_CREATE: &str = "
+CREATE TABLE IF NOT EXISTS migren_history (
+    entry_id BIGINT,
+    migration_id BIGINT,
+    name TEXT,
+    direction TEXT,
+    checksum TEXT,
+    started_at TEXT,
+    finished_at TEXT,
+    duration_ms BIGINT,
+    os_user TEXT,
+    hostname TEXT,
+    migren_version TEXT
+);
+";
+
+const REPEATABLE_TABLE_CREATE: &str = "
+CREATE TABLE IF NOT EXISTS migren_repeatable (
+    name TEXT,
+    checksum TEXT,
+    applied_at TEXT
+);
+";
+
+const SEEDS_TABLE_CREATE: &str = "
+CREATE TABLE IF NOT EXISTS migren_seeds (
+    seed_set TEXT,
+    name TEXT,
+    checksum TEXT,
+    applied_at TEXT
 );
 ";
 
+const ENVIRONMENT_TABLE_CREATE: &str = "
+CREATE TABLE IF NOT EXISTS migren_environment (
+    environment TEXT,
+    marked_at TEXT
+);
+";
+
+/// Value of `migren_environment` row of production database
+const PRODUCTION_ENVIRONMENT: &str = "production";
+
+/// Connects to database. Does not write anything, so it can be used with read-only credentials
 pub async fn connect(url: &str) -> Result<AnyConnection> {
     sqlx::any::install_default_drivers();
-    let mut conn = sqlx::AnyConnection::connect(url).await?;
-    info!("Connected to DB");
-
-    sqlx::query(TABLE_CREATE).execute(&mut conn).await?;
-    info!("Creating migren_data table if does not exists yet...");
+    let conn = sqlx::AnyConnection::connect(url).await?;
+    debug!("Connected to DB");
 
     Ok(conn)
 }
+
+/// Database migren may change freely: temporary SQLite file or database given by url.
+/// Never the database migrations are applied to
+pub struct ScratchDatabase {
+    pub conn: AnyConnection,
+    /// Temporary SQLite file removed by [`ScratchDatabase::close`]
+    file: Option<PathBuf>,
+}
+
+impl ScratchDatabase {
+    /// Connects to `url`, or creates temporary SQLite database if it is not set
+    pub async fn open(url: Option<&str>) -> Result<Self> {
+        if let Some(url) = url {
+            return Ok(Self {
+                conn: connect(url).await?,
+                file: None,
+            });
+        }
+
+        let file = std::env::temp_dir().join(format!(
+            "migren-scratch-{}-{}.sqlite",
+            std::process::id(),
+            Utc::now().timestamp_nanos_opt().unwrap_or_default()
+        ));
+        debug!("Using scratch database {file:?}");
+        let conn = connect(&format!("sqlite://{}?mode=rwc", file.display())).await?;
+        Ok(Self {
+            conn,
+            file: Some(file),
+        })
+    }
+
+    /// Closes connection and removes temporary SQLite file
+    pub async fn close(self) -> Result<()> {
+        self.conn.close().await?;
+        if let Some(file) = self.file
+            && fs::exists(&file)?
+        {
+            fs::remove_file(&file)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Returns true if table exists in current database (schema for Postgres)
+pub async fn table_exists(conn: &mut AnyConnection, table_name: &str) -> Result<bool> {
+    let backend = Backend::of(conn);
+    let query = match backend {
+        Backend::Postgres => {
+            "SELECT COUNT(*) FROM information_schema.tables WHERE table_schema = current_schema() AND table_name = $1"
+        }
+        Backend::MySql => {
+            "SELECT COUNT(*) FROM information_schema.tables WHERE table_schema = DATABASE() AND table_name = $1"
+        }
+        Backend::Sqlite => "SELECT COUNT(*) FROM sqlite_master WHERE type = 'table' AND name = $1",
+    };
+
+    let count = sqlx::query_scalar::<_, i64>(&backend.sql(query))
+        .bind(table_name)
+        .fetch_one(conn)
+        .await?;
+    Ok(count > 0)
+}
+
+/// Returns true if migren tables were created in database
+pub async fn is_initialized(conn: &mut AnyConnection) -> Result<bool> {
+    table_exists(conn, "migren_data").await
+}
+
+/// Returns true if `migren_data` of database created by older migren keeps migration id
+/// in `INTEGER` column, which is too small for timestamp ids. SQLite integers are always 64-bit
+pub async fn has_integer_migration_ids(conn: &mut AnyConnection) -> Result<bool> {
+    let schema = match Backend::of(conn) {
+        Backend::Postgres => "current_schema()",
+        Backend::MySql => "DATABASE()",
+        Backend::Sqlite => return Ok(false),
+    };
+
+    let data_type = sqlx::query_scalar::<_, String>(&format!(
+        "SELECT CAST(data_type AS CHAR(64)) FROM information_schema.columns WHERE table_schema = {schema} AND table_name = 'migren_data' AND column_name = 'last_migration_applied'"
+    ))
+    .fetch_optional(conn)
+    .await?;
+    Ok(data_type.is_some_and(|data_type| {
+        matches!(data_type.trim().to_lowercase().as_str(), "integer" | "int")
+    }))
+}
+
+/// Returns true if database was marked as production one by [`init`]
+pub async fn is_marked_production(conn: &mut AnyConnection) -> Result<bool> {
+    if !table_exists(conn, "migren_environment").await? {
+        return Ok(false);
+    }
+
+    let count = sqlx::query_scalar::<_, i64>(
+        &Backend::of(conn).sql("SELECT COUNT(*) FROM migren_environment WHERE environment = $1"),
+    )
+    .bind(PRODUCTION_ENVIRONMENT)
+    .fetch_one(conn)
+    .await?;
+    Ok(count > 0)
+}
+
+/// Creates migren tables if they do not exist yet. Only commands which change database call it,
+/// after taking migration lock, so concurrent migren instances do not create tables twice.
+/// With `production` marks database as production one. Mark is never removed, so clients
+/// without production environment still see it
+pub async fn init(conn: &mut AnyConnection, production: bool) -> Result<()> {
+    sqlx::query(TABLE_CREATE).execute(&mut *conn).await?;
+    debug!("Creating migren_data table if does not exists yet...");
+
+    sqlx::query(CHECKSUMS_TABLE_CREATE)
+        .execute(&mut *conn)
+        .await?;
+    debug!("Creating migren_checksums table if does not exists yet...");
+
+    sqlx::query(HISTORY_TABLE_CREATE)
+        .execute(&mut *conn)
+        .await?;
+    debug!("Creating migren_history table if does not exists yet...");
+
+    sqlx::query(IN_PROGRESS_TABLE_CREATE)
+        .execute(&mut *conn)
+        .await?;
+    debug!("Creating migren_in_progress table if does not exists yet...");
+
+    sqlx::query(REPEATABLE_TABLE_CREATE)
+        .execute(&mut *conn)
+        .await?;
+    debug!("Creating migren_repeatable table if does not exists yet...");
+
+    sqlx::query(SEEDS_TABLE_CREATE).execute(&mut *conn).await?;
+    debug!("Creating migren_seeds table if does not exists yet...");
+
+    sqlx::query(ENVIRONMENT_TABLE_CREATE)
+        .execute(&mut *conn)
+        .await?;
+    debug!("Creating migren_environment table if does not exists yet...");
+
+    let rows = sqlx::query_scalar::<_, i64>("SELECT COUNT(*) FROM migren_data")
+        .fetch_one(&mut *conn)
+        .await?;
+    if rows == 0 {
+        sqlx::query(&Backend::of(conn).sql(
+            "INSERT INTO migren_data (migren_version, last_migration_applied) VALUES ($1, 0)",
+        ))
+        .bind(env!("CARGO_PKG_VERSION"))
+        .execute(&mut *conn)
+        .await?;
+        debug!("Saved initial row into migren_data");
+    }
+
+    if production && !is_marked_production(conn).await? {
+        sqlx::query(
+            &Backend::of(conn)
+                .sql("INSERT INTO migren_environment (environment, marked_at) VALUES ($1, $2)"),
+        )
+        .bind(PRODUCTION_ENVIRONMENT)
+        .bind(timestamp())
+        .execute(&mut *conn)
+        .await?;
+        debug!("Marked database as production one");
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::test_connection;
+
+    #[tokio::test]
+    async fn keeps_production_mark() {
+        let mut conn = test_connection().await;
+        assert!(!is_marked_production(&mut conn).await.unwrap());
+
+        init(&mut conn, true).await.unwrap();
+        init(&mut conn, true).await.unwrap();
+        init(&mut conn, false).await.unwrap();
+        assert!(is_marked_production(&mut conn).await.unwrap());
+
+        let marks = sqlx::query_scalar::<_, i64>("SELECT COUNT(*) FROM migren_environment")
+            .fetch_one(&mut conn)
+            .await
+            .unwrap();
+        assert_eq!(marks, 1);
+    }
+}
@@ -1,15 +1,17 @@
-use log::debug;
 use regex::Regex;
 
 #[derive(Debug)]
 pub enum SqlDirective {
     Split,
+    /// Run whole file outside of transaction
+    NoTransaction,
 }
 
 impl SqlDirective {
     pub fn directive_regex(&self) -> Regex {
         match self {
             SqlDirective::Split => Regex::new(r"--.*migren:split.*").unwrap(),
+            SqlDirective::NoTransaction => Regex::new(r"--.*migren:no-transaction.*").unwrap(),
         }
     }
 
@@ -24,10 +26,22 @@ impl SqlDirective {
     }
 
     pub fn match_str(line: &str) -> Option<Self> {
-        [SqlDirective::Split.match_self_str(line)]
-            .into_iter()
-            .filter(|x| x.is_some())
-            .map(|x| x.unwrap())
-            .next()
+        [
+            SqlDirective::Split.match_self_str(line),
+            SqlDirective::NoTransaction.match_self_str(line),
+        ]
+        .into_iter()
+        .flatten()
+        .next()
     }
 }
+
+/// Returns false if sql code has `-- migren:no-transaction` directive
+pub fn is_transactional(sql_code: &str) -> bool {
+    !sql_code.lines().any(|line| {
+        matches!(
+            SqlDirective::match_str(line),
+            Some(SqlDirective::NoTransaction)
+        )
+    })
+}
@@ -2,5 +2,8 @@ use serde::Deserialize;
 
 #[derive(Deserialize, Debug)]
 pub struct EnvArgs {
-    pub database_url: String,
+    /// Not needed for commands which do not touch database
+    pub database_url: Option<String>,
+    /// `production` or `prod` marks database as production one
+    pub migren_environment: Option<String>,
 }
@@ -1,6 +1,12 @@
+use std::{path::PathBuf, time::Duration};
+
+use serde_json::{Value, json};
 use thiserror::Error;
 
-use crate::features::MigrationData;
+use crate::{
+    features::{MigrationData, MigrationDrift},
+    verify::VerifyIssue,
+};
 
 pub type Result<T> = std::result::Result<T, MigrenError>;
 
@@ -16,8 +22,139 @@ pub enum MigrenError {
     SerdeJson(#[from] serde_json::Error),
     #[error(transparent)]
     Database(#[from] sqlx::Error),
+    #[error("Statement {index} at line {line} of {file:?} failed")]
+    StatementFailed {
+        file: PathBuf,
+        index: usize,
+        line: usize,
+        source: sqlx::Error,
+    },
     #[error("Migration path from {from} to {to} is invalid. {comment}")]
-    MigrationPathInvalid { from: u32, to: u32, comment: String },
-    #[error("Migration files does not exists: {0:#?}")]
-    MigrationFilesDoesNotExsists(MigrationData),
+    MigrationPathInvalid { from: u64, to: u64, comment: String },
+    #[error("Nothing to merge. Migrations have only one head: {0:?}")]
+    NothingToMerge(Vec<u64>),
+    #[error(
+        "Files of migration {} ({}) do not exist: {:?}, {:?}",
+        .0.id, .0.name, .0.files.up_migration_file, .0.files.down_migration_file
+    )]
+    MigrationFilesDoesNotExsists(Box<MigrationData>),
+    #[error(
+        "Applied files of migrations {:?} were changed on disk. Use --allow-drift to ignore it or accept-checksums to save current checksums",
+        .0.iter().map(|drift| drift.id).collect::<Vec<_>>()
+    )]
+    MigrationDrift(Vec<MigrationDrift>),
+    #[error("Failed to acquire migration lock in {timeout:?}. Lock is held by {}", holder.as_deref().unwrap_or("unknown"))]
+    LockTimeout {
+        timeout: Duration,
+        holder: Option<String>,
+    },
+    #[error(
+        "Database is at migration {position}, which was squashed into migration {squashed_into}. Move database to migration before or after squashed range manually"
+    )]
+    SquashedMigration { position: u64, squashed_into: u64 },
+    #[error(
+        "Database is already at migration {0}. Baseline can be set only for database which was not migrated"
+    )]
+    AlreadyMigrated(u64),
+    #[error("Redo did not restore database state. {0}")]
+    RedoStateMismatch(String),
+    #[error("Round-trip test failed for migration {0}")]
+    TestFailed(u64),
+    #[error("Schema file {0:?} does not match database schema. Run `migren schema` to update it")]
+    SchemaStale(PathBuf),
+    #[error(
+        "Scratch database is not empty. Use empty database, migren drops everything it creates there"
+    )]
+    ScratchDatabaseNotEmpty,
+    #[error("Migration {0} does not exist in .migren.json")]
+    MigrationNotFound(u64),
+    #[error("Seed set directory {0:?} does not exist")]
+    SeedSetNotFound(PathBuf),
+    #[error(
+        "Seed set {0} can not be applied to production database. List it in production_seed_sets of .migren.json to allow it"
+    )]
+    ProductionSeedRefused(String),
+    #[error("Migrations verification failed with {} problems", .0.len())]
+    VerificationFailed(Vec<VerifyIssue>),
+    #[error("Database is not configured. Pass database url or connection to migrator")]
+    DatabaseNotConfigured,
+}
+
+impl MigrenError {
+    /// Name of error variant. Stable identifier for machine-readable output
+    pub fn kind(&self) -> &'static str {
+        match self {
+            MigrenError::Envy(_) => "Envy",
+            MigrenError::Clap(_) => "Clap",
+            MigrenError::Io(_) => "Io",
+            MigrenError::SerdeJson(_) => "SerdeJson",
+            MigrenError::Database(_) => "Database",
+            MigrenError::StatementFailed { .. } => "StatementFailed",
+            MigrenError::MigrationPathInvalid { .. } => "MigrationPathInvalid",
+            MigrenError::NothingToMerge(_) => "NothingToMerge",
+            MigrenError::MigrationFilesDoesNotExsists(_) => "MigrationFilesDoesNotExsists",
+            MigrenError::MigrationDrift(_) => "MigrationDrift",
+            MigrenError::LockTimeout { .. } => "LockTimeout",
+            MigrenError::SquashedMigration { .. } => "SquashedMigration",
+            MigrenError::AlreadyMigrated(_) => "AlreadyMigrated",
+            MigrenError::RedoStateMismatch(_) => "RedoStateMismatch",
+            MigrenError::TestFailed(_) => "TestFailed",
+            MigrenError::SchemaStale(_) => "SchemaStale",
+            MigrenError::ScratchDatabaseNotEmpty => "ScratchDatabaseNotEmpty",
+            MigrenError::MigrationNotFound(_) => "MigrationNotFound",
+            MigrenError::SeedSetNotFound(_) => "SeedSetNotFound",
+            MigrenError::ProductionSeedRefused(_) => "ProductionSeedRefused",
+            MigrenError::VerificationFailed(_) => "VerificationFailed",
+            MigrenError::DatabaseNotConfigured => "DatabaseNotConfigured",
+        }
+    }
+
+    /// Error as JSON object with its kind, message and fields of variant
+    pub fn to_json(&self) -> Value {
+        let fields = match self {
+            MigrenError::StatementFailed {
+                file,
+                index,
+                line,
+                source,
+            } => json!({
+                "file": file,
+                "index": index,
+                "line": line,
+                "cause": source.to_string(),
+            }),
+            MigrenError::MigrationPathInvalid { from, to, comment } => {
+                json!({ "from": from, "to": to, "comment": comment })
+            }
+            MigrenError::NothingToMerge(heads) => json!({ "heads": heads }),
+            MigrenError::MigrationFilesDoesNotExsists(migration) => {
+                json!({ "migration": migration })
+            }
+            MigrenError::MigrationDrift(drift) => json!({ "drift": drift }),
+            MigrenError::LockTimeout { timeout, holder } => {
+                json!({ "timeout_secs": timeout.as_secs(), "holder": holder })
+            }
+            MigrenError::SquashedMigration {
+                position,
+                squashed_into,
+            } => json!({ "position": position, "squashed_into": squashed_into }),
+            MigrenError::AlreadyMigrated(position) => json!({ "position": position }),
+            MigrenError::RedoStateMismatch(comment) => json!({ "comment": comment }),
+            MigrenError::TestFailed(id) => json!({ "migration_id": id }),
+            MigrenError::SchemaStale(file) => json!({ "file": file }),
+            MigrenError::MigrationNotFound(id) => json!({ "migration_id": id }),
+            MigrenError::SeedSetNotFound(directory) => json!({ "directory": directory }),
+            MigrenError::ProductionSeedRefused(set) => json!({ "set": set }),
+            MigrenError::VerificationFailed(issues) => json!({
+                "issues": issues.iter().map(|issue| issue.to_string()).collect::<Vec<_>>(),
+            }),
+            _ => json!({}),
+        };
+
+        let mut error = json!({ "kind": self.kind(), "message": self.to_string() });
+        if let (Value::Object(error), Value::Object(fields)) = (&mut error, fields) {
+            error.extend(fields);
+        }
+        error
+    }
 }
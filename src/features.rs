@@ -1,11 +1,31 @@
-use std::{collections::HashSet, path::PathBuf};
+use std::{
+    cmp::Reverse,
+    collections::{BinaryHeap, HashMap, HashSet},
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
 
+use chrono::Utc;
 use log::{debug, info, warn};
-use serde::{Deserialize, Serialize};
-use sqlx::Connection;
+use serde::{Deserialize, Deserializer, Serialize};
+use sqlx::{AnyConnection, Connection};
 
 use crate::{
-    derictive_constants::SqlDirective, errors::{MigrenError, Result}, util::{assert_migration_files_exists, create_migration_files}
+    database::{init, is_initialized, table_exists},
+    derictive_constants::is_transactional,
+    errors::{MigrenError, Result},
+    history::{
+        BASELINE_DIRECTION, DatabaseHistoryEntry, Direction, history, hostname,
+        insert_history_entry, os_user, timestamp,
+    },
+    lock::{DEFAULT_LOCK_TIMEOUT, lock, unlock},
+    repeatable::{RepeatableToApply, apply_repeatable, pending_repeatables},
+    schema::{Backend, schema_snapshot},
+    splitter::{Statement, split_sql},
+    util::{
+        DiffLine, assert_migration_files_exists, checksum, create_migration_files, diff_lines,
+        file_checksum, save_migrations_data,
+    },
 };
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -24,18 +44,168 @@ pub struct MigrationData {
     /// Migration name
     pub name: String,
     /// Migration id
-    pub id: u32,
-    /// Previous migration
-    pub prev_migration_id: Option<u32>,
-    /// Next migration
-    pub next_migration_id: Option<u32>,
+    pub id: u64,
+    /// Previous migrations. More than one for merge migration
+    #[serde(
+        alias = "prev_migration_id",
+        deserialize_with = "migration_ids",
+        default
+    )]
+    pub prev_migration_ids: Vec<u64>,
+    /// Next migrations. More than one if migrations diverged
+    #[serde(
+        alias = "next_migration_id",
+        deserialize_with = "migration_ids",
+        default
+    )]
+    pub next_migration_ids: Vec<u64>,
+}
+
+/// Reads migration links both from id list and from single optional id,
+/// which was used before migrations could diverge
+fn migration_ids<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> std::result::Result<Vec<u64>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum MigrationIds {
+        Single(Option<u64>),
+        Many(Vec<u64>),
+    }
+
+    Ok(match MigrationIds::deserialize(deserializer)? {
+        MigrationIds::Single(id) => id.into_iter().collect(),
+        MigrationIds::Many(ids) => ids,
+    })
 }
 
 /// Holds data about migration from path.
 #[derive(Debug)]
 pub struct MigrationToApply {
-    pub id: u32,
+    pub id: u64,
     pub file: PathBuf,
+    /// Up file is applied, down file is reverted
+    pub direction: Direction,
+}
+
+impl MigrationToApply {
+    /// Reads migration file and splits it into statements, which are executed one by one
+    pub fn statements(&self, backend: Backend) -> Result<Vec<Statement>> {
+        Ok(split_sql(&std::fs::read_to_string(&self.file)?, backend))
+    }
+
+    /// Returns false if file has `-- migren:no-transaction` directive
+    pub fn is_transactional(&self) -> Result<bool> {
+        Ok(is_transactional(&std::fs::read_to_string(&self.file)?))
+    }
+}
+
+/// Result of moving database between migrations
+#[derive(Debug)]
+pub struct MigrationReport {
+    /// Migration database was at before the run
+    pub from: u64,
+    /// Migration database is at after the run
+    pub to: u64,
+    /// Applied migration files in order of execution. Planned ones for dry run
+    pub applied: Vec<MigrationToApply>,
+    /// Nothing was executed, database is still at `from` migration
+    pub dry_run: bool,
+    /// Changed repeatable files applied after migration files. Planned ones for dry run
+    pub repeatables: Vec<RepeatableToApply>,
+    /// Backend of migrated database. Decides how files are split into statements
+    pub backend: Backend,
+}
+
+/// Result of reverting and reapplying last migrations
+#[derive(Debug)]
+pub struct RedoReport {
+    /// Reverting of migrations
+    pub down: MigrationReport,
+    /// Applying of reverted migrations back
+    pub up: MigrationReport,
+}
+
+/// Applied migration whose up file differs from what was applied
+#[derive(Serialize, Debug, Clone)]
+pub struct MigrationDrift {
+    pub id: u64,
+    /// Up file of migration. `None` if migration is not present in `.migren.json`
+    pub file: Option<PathBuf>,
+    /// Checksum saved in database when migration was applied
+    pub applied_checksum: String,
+    /// Checksum of file on disk. `None` if file does not exist
+    pub current_checksum: Option<String>,
+}
+
+/// Options for moving database between migrations
+#[derive(Debug, Clone)]
+pub struct MigrateOptions {
+    /// Apply migrations even if already applied files were changed
+    pub allow_drift: bool,
+    /// Only build migration path, without executing anything
+    pub dry_run: bool,
+    /// How long to wait for migration lock held by another migren
+    pub lock_timeout: Duration,
+    /// Database is production one. It is marked as production in database, and only base
+    /// seed set and sets allowed by `.migren.json` are applied to it
+    pub production: bool,
+}
+
+impl Default for MigrateOptions {
+    fn default() -> Self {
+        Self {
+            allow_drift: false,
+            dry_run: false,
+            lock_timeout: DEFAULT_LOCK_TIMEOUT,
+            production: false,
+        }
+    }
+}
+
+/// How ids of new migrations are generated
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum IdFormat {
+    /// Next number after the biggest migration id
+    #[default]
+    Counter,
+    /// UTC time of migration creation, like `20261018143000`
+    Timestamp,
+}
+
+/// Migration which was squashed into another one
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SquashedMigration {
+    pub id: u64,
+    pub name: String,
+    /// Checksum of up file, so databases migrated with it do not show drift
+    pub checksum: String,
+}
+
+/// Range of migrations squashed into single migration by [`MigrationsData::squash`]
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Squash {
+    /// Migration range was squashed into. It has id of the last squashed migration
+    pub id: u64,
+    /// Squashed migrations in order they were applied
+    pub migrations: Vec<SquashedMigration>,
+}
+
+/// Code of migration file inside of squashed file. Split directive keeps statement without
+/// trailing delimiter apart from the next file, and delimiter changed by MySQL `DELIMITER`
+/// is reset for it
+fn squashed_file_part(migration: &MigrationData, sql_code: &str) -> String {
+    let header = format!("-- migren:split {} - {}", migration.id, migration.name);
+    let changes_delimiter = sql_code.lines().any(|line| {
+        line.trim_start()
+            .get(..10)
+            .is_some_and(|word| word.eq_ignore_ascii_case("delimiter "))
+    });
+    match changes_delimiter {
+        true => format!("{header}\n{}\nDELIMITER ;\n", sql_code.trim_end()),
+        false => format!("{header}\n{}\n", sql_code.trim_end()),
+    }
 }
 
 /// Holds every migration. Root object for .migren.json file
@@ -43,8 +213,20 @@ pub struct MigrationToApply {
 pub struct MigrationsData {
     pub migrations: Vec<MigrationData>,
     pub migren_version: String,
-    pub migrations_start_id: Option<u32>,
-    pub migrations_counter: u32,
+    pub migrations_start_id: Option<u64>,
+    pub migrations_counter: u64,
+    /// How ids of new migrations are generated
+    #[serde(default)]
+    pub id_format: IdFormat,
+    /// Ranges of migrations squashed into one migration
+    #[serde(default)]
+    pub squashes: Vec<Squash>,
+    /// Seed sets which can be applied to production database besides base set
+    #[serde(default)]
+    pub production_seed_sets: Vec<String>,
+    /// Migrations directory. Every migration file is resolved against it
+    #[serde(skip)]
+    pub directory: PathBuf,
 }
 
 impl Default for MigrationsData {
@@ -57,52 +239,301 @@ impl Default for MigrationsData {
                 },
                 name: "initial".to_string(),
                 id: 0,
-                prev_migration_id: None,
-                next_migration_id: None,
+                prev_migration_ids: Vec::new(),
+                next_migration_ids: Vec::new(),
             }],
             migrations_start_id: None,
             migren_version: env!("CARGO_PKG_VERSION").to_string(),
             migrations_counter: 0,
+            id_format: IdFormat::default(),
+            squashes: Vec::new(),
+            production_seed_sets: Vec::new(),
+            directory: PathBuf::new(),
         }
     }
 }
 
 impl MigrationsData {
-    pub fn migration_by_id(&self, id: u32) -> Option<&MigrationData> {
+    pub fn migration_by_id(&self, id: u64) -> Option<&MigrationData> {
         self.migrations.iter().find(|migration| migration.id == id)
     }
 
-    pub fn migration_by_id_mut(&mut self, id: u32) -> Option<&mut MigrationData> {
+    pub fn migration_by_id_mut(&mut self, id: u64) -> Option<&mut MigrationData> {
         self.migrations
             .iter_mut()
             .find(|migration| migration.id == id)
     }
 
-    /// Create new migrations files + modify migrations_data
-    pub fn new_migration(&mut self, migration_name: &str) -> Result<&MigrationData> {
-        let migration_id = self.migrations_counter + 1;
-        let last_migration_id = self
-            .migration_by_id(self.migrations_counter)
-            .map(|migration| migration.id);
+    /// Migrations which have no next migrations. More than one means migrations diverged
+    /// and should be merged with [`MigrationsData::merge_heads`]
+    pub fn heads(&self) -> Vec<u64> {
+        let mut heads: Vec<u64> = self
+            .migrations
+            .iter()
+            .filter(|migration| migration.next_migration_ids.is_empty())
+            .map(|migration| migration.id)
+            .collect();
+        heads.sort();
+        heads
+    }
+
+    /// Returns migration and every migration before it
+    pub fn ancestors(&self, migration_id: u64) -> Result<HashSet<u64>> {
+        let mut ancestors = HashSet::new();
+        let mut to_visit = vec![migration_id];
 
-        info!("New migration id is {migration_id}");
-        info!("Found last migration: {last_migration_id:?}");
-        let migration_files = create_migration_files(migration_id, migration_name)?;
+        while let Some(id) = to_visit.pop() {
+            if !ancestors.insert(id) {
+                continue;
+            }
+
+            let migration =
+                self.migration_by_id(id)
+                    .ok_or_else(|| MigrenError::MigrationPathInvalid {
+                        from: migration_id,
+                        to: id,
+                        comment: format!("Previous migration {id} not found"),
+                    })?;
+            to_visit.extend(&migration.prev_migration_ids);
+        }
+
+        Ok(ancestors)
+    }
+
+    /// Orders migrations so every migration goes after all of its previous migrations.
+    /// Migrations which do not depend on each other are ordered by id
+    pub fn topological_order(&self, ids: &HashSet<u64>) -> Result<Vec<u64>> {
+        let mut remaining_prev: HashMap<u64, usize> = HashMap::with_capacity(ids.len());
+        let mut ready = BinaryHeap::new();
+
+        for id in ids {
+            let prev_count = self
+                .migration_by_id(*id)
+                .map(|migration| {
+                    migration
+                        .prev_migration_ids
+                        .iter()
+                        .filter(|prev_id| ids.contains(prev_id))
+                        .count()
+                })
+                .unwrap_or(0);
+
+            if prev_count == 0 {
+                ready.push(Reverse(*id));
+            }
+            remaining_prev.insert(*id, prev_count);
+        }
+
+        let mut order = Vec::with_capacity(ids.len());
+        while let Some(Reverse(id)) = ready.pop() {
+            order.push(id);
+
+            let next_ids = self
+                .migration_by_id(id)
+                .map(|migration| migration.next_migration_ids.as_slice())
+                .unwrap_or_default();
+            for next_id in next_ids {
+                if let Some(count) = remaining_prev.get_mut(next_id) {
+                    *count -= 1;
+                    if *count == 0 {
+                        ready.push(Reverse(*next_id));
+                    }
+                }
+            }
+        }
+
+        if order.len() != ids.len() {
+            let circular: Vec<&u64> = ids.iter().filter(|id| !order.contains(id)).collect();
+            return Err(MigrenError::MigrationPathInvalid {
+                from: order.first().copied().unwrap_or(0),
+                to: order.last().copied().unwrap_or(0),
+                comment: format!("Circular migration found between migrations {circular:?}"),
+            });
+        }
+
+        Ok(order)
+    }
+
+    /// Returns migrations from the first one to `last_applied` (inclusive), skipping 0 migration
+    pub fn applied_migrations(&self, last_applied: u64) -> Result<Vec<&MigrationData>> {
+        let ancestors = self.ancestors(last_applied)?;
+
+        Ok(self
+            .topological_order(&ancestors)?
+            .into_iter()
+            .filter(|id| *id != 0)
+            .filter_map(|id| self.migration_by_id(id))
+            .collect())
+    }
+
+    /// Migration which has exactly `applied` migrations as its ancestors (skipping 0 migration)
+    pub fn position_of(&self, applied: &HashSet<u64>) -> Option<u64> {
+        let mut tips = applied.iter().filter(|id| {
+            self.migration_by_id(**id).is_some_and(|migration| {
+                !migration
+                    .next_migration_ids
+                    .iter()
+                    .any(|next_id| applied.contains(next_id))
+            })
+        });
+
+        let tip = *tips.next()?;
+        if tips.next().is_some() {
+            return None;
+        }
+
+        let mut ancestors = self.ancestors(tip).ok()?;
+        ancestors.remove(&0);
+        (ancestors == *applied).then_some(tip)
+    }
+
+    /// Migration database is at after applying migration in direction
+    pub fn position_after(&self, migration_id: u64, direction: Direction) -> u64 {
+        match direction {
+            Direction::Up => migration_id,
+            Direction::Down => self
+                .migration_by_id(migration_id)
+                .and_then(|migration| migration.prev_migration_ids.first().copied())
+                .unwrap_or(0),
+        }
+    }
+
+    /// Migration database is at after reverting `steps` migrations from `from`.
+    /// Fails on merge migration, because reverting it alone leaves several branches applied
+    pub fn steps_back(&self, from: u64, steps: u64) -> Result<u64> {
+        self.assert_not_squashed(from)?;
+        let mut position = from;
+        for step in 0..steps {
+            if position == 0 {
+                return Err(MigrenError::MigrationPathInvalid {
+                    from,
+                    to: 0,
+                    comment: format!("Only {step} migrations can be reverted, {steps} requested"),
+                });
+            }
+
+            let prev_ids = self
+                .migration_by_id(position)
+                .map(|migration| migration.prev_migration_ids.as_slice())
+                .unwrap_or_default();
+            if prev_ids.len() > 1 {
+                return Err(MigrenError::MigrationPathInvalid {
+                    from,
+                    to: position,
+                    comment: format!(
+                        "Migration {position} merges migrations {prev_ids:?}. Use `to` to select migration to move to"
+                    ),
+                });
+            }
+            position = self.position_after(position, Direction::Down);
+        }
+
+        Ok(position)
+    }
+
+    /// Migration database is at after applying `steps` next migrations from `from`.
+    /// Fails if migrations diverged, because next migration is ambiguous then
+    pub fn steps_forward(&self, from: u64, steps: u64) -> Result<u64> {
+        self.assert_not_squashed(from)?;
+        let mut position = from;
+        for step in 0..steps {
+            let next_ids = self
+                .migration_by_id(position)
+                .map(|migration| migration.next_migration_ids.as_slice())
+                .unwrap_or_default();
+
+            position = match next_ids {
+                [] => {
+                    return Err(MigrenError::MigrationPathInvalid {
+                        from,
+                        to: position,
+                        comment: format!(
+                            "Only {step} migrations can be applied, {steps} requested"
+                        ),
+                    });
+                }
+                [next_id] => *next_id,
+                _ => {
+                    return Err(MigrenError::MigrationPathInvalid {
+                        from,
+                        to: position,
+                        comment: format!(
+                            "Migration {position} has several next migrations {next_ids:?}. Use `to` to select one"
+                        ),
+                    });
+                }
+            };
+        }
+
+        Ok(position)
+    }
+
+    /// Path to up file of migration, resolved against migrations directory
+    pub fn up_file(&self, migration: &MigrationData) -> PathBuf {
+        self.directory.join(&migration.files.up_migration_file)
+    }
+
+    /// Path to down file of migration, resolved against migrations directory
+    pub fn down_file(&self, migration: &MigrationData) -> PathBuf {
+        self.directory.join(&migration.files.down_migration_file)
+    }
+
+    /// Id for new migration
+    fn next_migration_id(&self) -> u64 {
+        match self.id_format {
+            IdFormat::Counter => {
+                self.migrations
+                    .iter()
+                    .map(|migration| migration.id)
+                    .max()
+                    .unwrap_or(0)
+                    .max(self.migrations_counter)
+                    + 1
+            }
+            IdFormat::Timestamp => {
+                let mut migration_id = Utc::now()
+                    .format("%Y%m%d%H%M%S")
+                    .to_string()
+                    .parse()
+                    .unwrap();
+                // Several migrations created in the same second
+                while self.migration_by_id(migration_id).is_some() {
+                    migration_id += 1;
+                }
+                migration_id
+            }
+        }
+    }
+
+    /// Creates migration files and links migration after `prev_migration_ids`
+    fn add_migration(
+        &mut self,
+        migration_name: &str,
+        prev_migration_ids: Vec<u64>,
+    ) -> Result<&MigrationData> {
+        let migration_id = self.next_migration_id();
+
+        debug!("New migration id is {migration_id}");
+        let migration_files =
+            create_migration_files(&self.directory, migration_id, migration_name)?;
 
         let migration = MigrationData {
             files: migration_files,
             name: migration_name.to_string(),
             id: migration_id,
-            prev_migration_id: last_migration_id,
-            next_migration_id: Option::None,
+            prev_migration_ids: prev_migration_ids.clone(),
+            next_migration_ids: Vec::new(),
         };
 
-        info!("New migration data: {migration:#?}");
+        debug!("New migration data: {migration:#?}");
 
-        if let Some(last_migration) = self.migration_by_id_mut(self.migrations_counter) {
-            last_migration.next_migration_id = Some(migration_id);
-            info!("Changed last migration refs: {last_migration:#?}");
-        } else {
+        for prev_id in &prev_migration_ids {
+            if let Some(prev_migration) = self.migration_by_id_mut(*prev_id) {
+                prev_migration.next_migration_ids.push(migration_id);
+                debug!("Changed previous migration refs: {prev_migration:#?}");
+            }
+        }
+        if prev_migration_ids.is_empty() {
             self.migrations_start_id = Some(migration_id);
         }
 
@@ -112,104 +543,210 @@ impl MigrationsData {
         Ok(self.migration_by_id(migration_id).unwrap())
     }
 
-    pub fn build_migration_path_down(
-        &self,
-        start: &MigrationData,
-        stop: &MigrationData,
-    ) -> Result<Vec<MigrationToApply>> {
-        let mut found_migrations =
-            HashSet::<u32>::with_capacity((stop.id as usize).abs_diff(start.id as usize));
-
-        let mut on_migration = start;
-        let mut path = Vec::with_capacity((stop.id as usize).abs_diff(start.id as usize));
-
-        while on_migration.id != stop.id {
-            // Skipping 0 migration
-            if on_migration.id != 0 {
-                assert_migration_files_exists(on_migration)?;
-                path.push(MigrationToApply {
-                    id: on_migration.id,
-                    file: on_migration.files.down_migration_file.clone(),
-                });
-            }
-
-            if found_migrations.contains(&on_migration.id) {
-                return Err(MigrenError::MigrationPathInvalid {
-                    from: start.id,
-                    to: stop.id,
-                    comment: format!("Circular migration found: {on_migration:#?}"),
-                });
-            }
-            found_migrations.insert(on_migration.id);
+    /// Create new migrations files + modify migrations_data
+    pub fn new_migration(&mut self, migration_name: &str) -> Result<&MigrationData> {
+        let last_migration_id = self
+            .migration_by_id(self.migrations_counter)
+            .map(|migration| migration.id);
+        debug!("Found last migration: {last_migration_id:?}");
 
-            let next_migration = on_migration
-                .prev_migration_id
-                .and_then(|next_id| self.migration_by_id(next_id));
+        self.add_migration(migration_name, last_migration_id.into_iter().collect())
+    }
 
-            if next_migration.is_none() {
-                return Err(MigrenError::MigrationPathInvalid {
-                    from: start.id,
-                    to: stop.id,
-                    comment: format!(
-                        "Next migration not found. Was on migration {on_migration:#?}"
-                    ),
-                });
-            }
-            on_migration = next_migration.unwrap();
+    /// Create merge migration after every head, so diverged migrations can be applied together
+    pub fn merge_heads(&mut self, migration_name: &str) -> Result<&MigrationData> {
+        let heads = self.heads();
+        if heads.len() < 2 {
+            return Err(MigrenError::NothingToMerge(heads));
         }
-        Ok(path)
+
+        debug!("Merging heads {heads:?}");
+        self.add_migration(migration_name, heads)
     }
 
-    pub fn build_migration_path_up(
-        &self,
-        start: &MigrationData,
-        stop: &MigrationData,
-    ) -> Result<Vec<MigrationToApply>> {
-        let mut found_migrations =
-            HashSet::<u32>::with_capacity((stop.id as usize).abs_diff(start.id as usize));
+    /// Squashes migrations from `from` to `to` (inclusive) into single migration with `to` id,
+    /// so databases at `to` or after it are already at squashed migration.
+    /// Up files are concatenated in applying order, down files in reverse order, every file
+    /// starting with its own statement and ending with default delimiter.
+    /// Writes new files and `.migren.json` before removing files of squashed migrations
+    pub fn squash(&mut self, from: u64, to: u64, migration_name: &str) -> Result<&MigrationData> {
+        let invalid = |comment: String| MigrenError::MigrationPathInvalid { from, to, comment };
 
-        let mut on_migration = start;
-        let mut path = Vec::with_capacity((stop.id as usize).abs_diff(start.id as usize));
+        if from == 0 || to == 0 {
+            return Err(invalid("Initial migration can not be squashed".to_string()));
+        }
+        let target = self.ancestors(to)?;
+        if !target.contains(&from) {
+            return Err(invalid(format!(
+                "Migration {from} is not before migration {to}"
+            )));
+        }
 
-        while on_migration.id != stop.id {
-            if found_migrations.contains(&on_migration.id) {
-                return Err(MigrenError::MigrationPathInvalid {
-                    from: start.id,
-                    to: stop.id,
-                    comment: format!("Circular migration found: {on_migration:#?}"),
-                });
+        let mut range: HashSet<u64> = target.difference(&self.ancestors(from)?).copied().collect();
+        range.insert(from);
+
+        for id in &range {
+            let migration = self.migration_by_id(*id).unwrap();
+            if *id != from
+                && let Some(prev_id) = migration
+                    .prev_migration_ids
+                    .iter()
+                    .find(|prev_id| !range.contains(prev_id))
+            {
+                return Err(invalid(format!(
+                    "Migration {id} depends on migration {prev_id} outside of squashed range"
+                )));
+            }
+            if *id != to
+                && let Some(next_id) = migration
+                    .next_migration_ids
+                    .iter()
+                    .find(|next_id| !range.contains(next_id))
+            {
+                return Err(invalid(format!(
+                    "Migration {next_id} outside of squashed range depends on migration {id}"
+                )));
             }
-            found_migrations.insert(on_migration.id);
+        }
 
-            let next_migration = on_migration
-                .next_migration_id
-                .and_then(|next_id| self.migration_by_id(next_id));
+        let order = self.topological_order(&range)?;
+        let mut up_sql = Vec::with_capacity(order.len());
+        let mut down_sql = Vec::with_capacity(order.len());
+        let mut squashed = Vec::with_capacity(order.len());
+        let mut old_files = Vec::with_capacity(order.len() * 2);
+        for id in &order {
+            let migration = self.migration_by_id(*id).unwrap();
+            assert_migration_files_exists(&self.directory, migration)?;
 
-            if next_migration.is_none() {
-                return Err(MigrenError::MigrationPathInvalid {
-                    from: start.id,
-                    to: stop.id,
-                    comment: format!(
-                        "Next migration not found. Was on migration {on_migration:#?}"
-                    ),
-                });
+            let up_code = std::fs::read_to_string(self.up_file(migration))?;
+            let down_code = std::fs::read_to_string(self.down_file(migration))?;
+            up_sql.push(squashed_file_part(migration, &up_code));
+            down_sql.push(squashed_file_part(migration, &down_code));
+
+            squashed.push(SquashedMigration {
+                id: migration.id,
+                name: migration.name.clone(),
+                checksum: checksum(&up_code),
+            });
+            old_files.push(self.up_file(migration));
+            old_files.push(self.down_file(migration));
+        }
+        down_sql.reverse();
+
+        // Transaction directive is set for whole file, so it can not be kept for part of it
+        for (kind, parts) in [("up", &up_sql), ("down", &down_sql)] {
+            if parts.iter().any(|part| is_transactional(part))
+                && parts.iter().any(|part| !is_transactional(part))
+            {
+                return Err(invalid(format!(
+                    "Range mixes transactional and non-transactional {kind} files. Squash them separately"
+                )));
             }
-            on_migration = next_migration.unwrap();
-            // Skipping 0 migration
-            if on_migration.id != 0 {
-                assert_migration_files_exists(on_migration)?;
-                path.push(MigrationToApply {
-                    id: on_migration.id,
-                    file: on_migration.files.up_migration_file.clone(),
-                });
+        }
+
+        let files = create_migration_files(&self.directory, to, migration_name)?;
+        std::fs::write(
+            self.directory.join(&files.up_migration_file),
+            up_sql.join("\n"),
+        )?;
+        std::fs::write(
+            self.directory.join(&files.down_migration_file),
+            down_sql.join("\n"),
+        )?;
+        let new_files = [
+            self.directory.join(&files.up_migration_file),
+            self.directory.join(&files.down_migration_file),
+        ];
+
+        let prev_migration_ids = self
+            .migration_by_id(from)
+            .unwrap()
+            .prev_migration_ids
+            .clone();
+        let next_migration_ids = self.migration_by_id(to).unwrap().next_migration_ids.clone();
+        for prev_id in &prev_migration_ids {
+            let prev_migration = self.migration_by_id_mut(*prev_id).unwrap();
+            for next_id in prev_migration.next_migration_ids.iter_mut() {
+                if *next_id == from {
+                    *next_id = to;
+                }
             }
         }
-        Ok(path)
+
+        let index = self
+            .migrations
+            .iter()
+            .position(|migration| range.contains(&migration.id))
+            .unwrap();
+        self.migrations
+            .retain(|migration| !range.contains(&migration.id));
+        self.migrations.insert(
+            index,
+            MigrationData {
+                files,
+                name: migration_name.to_string(),
+                id: to,
+                prev_migration_ids,
+                next_migration_ids,
+            },
+        );
+
+        if range.contains(&self.migrations_counter) {
+            self.migrations_counter = to;
+        }
+        if self
+            .migrations_start_id
+            .is_some_and(|start_id| range.contains(&start_id))
+        {
+            self.migrations_start_id = Some(to);
+        }
+        self.squashes.push(Squash {
+            id: to,
+            migrations: squashed,
+        });
+        save_migrations_data(self)?;
+
+        for file in old_files.iter().filter(|file| !new_files.contains(file)) {
+            std::fs::remove_file(file)?;
+        }
+        info!("Removed files of squashed migrations {order:?}");
+
+        Ok(self.migration_by_id(to).unwrap())
+    }
+
+    /// Migration `migration_id` was squashed into. `None` if it was not squashed
+    /// or if it is the migration range was squashed into
+    pub fn squashed_into(&self, migration_id: u64) -> Option<u64> {
+        self.squashes
+            .iter()
+            .rev()
+            .find(|squash| {
+                squash.id != migration_id
+                    && squash
+                        .migrations
+                        .iter()
+                        .any(|migration| migration.id == migration_id)
+            })
+            .map(|squash| squash.id)
+    }
+
+    /// Fails if database at `position` is in the middle of squashed range
+    fn assert_not_squashed(&self, position: u64) -> Result<()> {
+        match self.squashed_into(position) {
+            Some(squashed_into) => Err(MigrenError::SquashedMigration {
+                position,
+                squashed_into,
+            }),
+            None => Ok(()),
+        }
     }
 
-    pub fn build_migration_path(&self, from: u32, to: u32) -> Result<Vec<MigrationToApply>> {
-        let from_migration = self.migration_by_id(from);
-        if from_migration.is_none() {
+    /// Builds path between migrations. Reverts every applied migration which is not
+    /// before `to` migration, then applies every migration before `to` which is not applied
+    pub fn build_migration_path(&self, from: u64, to: u64) -> Result<Vec<MigrationToApply>> {
+        self.assert_not_squashed(from)?;
+
+        if self.migration_by_id(from).is_none() {
             return Err(MigrenError::MigrationPathInvalid {
                 from,
                 to,
@@ -217,28 +754,581 @@ impl MigrationsData {
             });
         }
 
-        let to_migration = self.migration_by_id(to);
-        if to_migration.is_none() {
+        if self.migration_by_id(to).is_none() {
             return Err(MigrenError::MigrationPathInvalid {
                 from,
                 to,
                 comment: "to migration does not exists".to_string(),
             });
         }
-        let start = from_migration.unwrap();
-        let stop = to_migration.unwrap();
-        if start.id < stop.id {
-            self.build_migration_path_up(start, stop)
+
+        let applied = self.ancestors(from)?;
+        let target = self.ancestors(to)?;
+        let order = self.topological_order(&applied.union(&target).copied().collect())?;
+
+        let down = order
+            .iter()
+            .rev()
+            .filter(|id| applied.contains(id) && !target.contains(id))
+            .map(|id| (*id, Direction::Down));
+        let up = order
+            .iter()
+            .filter(|id| target.contains(id) && !applied.contains(id))
+            .map(|id| (*id, Direction::Up));
+
+        let mut path = Vec::new();
+        // Skipping 0 migration
+        for (id, direction) in down.chain(up).filter(|(id, _)| *id != 0) {
+            let migration = self.migration_by_id(id).unwrap();
+            assert_migration_files_exists(&self.directory, migration)?;
+
+            path.push(MigrationToApply {
+                id,
+                file: match direction {
+                    Direction::Up => self.up_file(migration),
+                    Direction::Down => self.down_file(migration),
+                },
+                direction,
+            });
+        }
+
+        Ok(path)
+    }
+}
+
+/// Executes statements of sql file one by one
+pub(crate) async fn execute_statements(
+    conn: &mut AnyConnection,
+    file: &Path,
+    sql_code: &str,
+) -> Result<()> {
+    let backend = Backend::of(conn);
+    for (index, statement) in split_sql(sql_code, backend).into_iter().enumerate() {
+        sqlx::query(&statement.sql)
+            .execute(&mut *conn)
+            .await
+            .map_err(|source| MigrenError::StatementFailed {
+                file: file.to_path_buf(),
+                index: index + 1,
+                line: statement.line,
+                source,
+            })?;
+    }
+
+    Ok(())
+}
+
+/// Saves applied migration files into history and checksums tables
+struct HistoryRecorder<'a> {
+    migrations_data: &'a MigrationsData,
+    os_user: String,
+    hostname: String,
+}
+
+impl HistoryRecorder<'_> {
+    /// Executes migration file and records it
+    async fn apply(
+        &self,
+        conn: &mut AnyConnection,
+        migration: &MigrationToApply,
+        sql_code: &str,
+    ) -> Result<()> {
+        let started_at = timestamp();
+        let started = Instant::now();
+
+        execute_statements(conn, &migration.file, sql_code).await?;
+
+        insert_history_entry(
+            conn,
+            &DatabaseHistoryEntry {
+                migration_id: migration.id as i64,
+                name: self
+                    .migrations_data
+                    .migration_by_id(migration.id)
+                    .map(|migration| migration.name.clone())
+                    .unwrap_or_default(),
+                direction: migration.direction.as_str().to_string(),
+                checksum: checksum(sql_code),
+                started_at,
+                finished_at: timestamp(),
+                duration_ms: started.elapsed().as_millis() as i64,
+                os_user: self.os_user.clone(),
+                hostname: self.hostname.clone(),
+                migren_version: env!("CARGO_PKG_VERSION").to_string(),
+            },
+        )
+        .await?;
+
+        sqlx::query(&Backend::of(conn).sql("DELETE FROM migren_checksums WHERE migration_id = $1"))
+            .bind(migration.id as i64)
+            .execute(&mut *conn)
+            .await?;
+        if migration.direction == Direction::Up {
+            sqlx::query(
+                &Backend::of(conn)
+                    .sql("INSERT INTO migren_checksums (migration_id, checksum) VALUES ($1, $2)"),
+            )
+            .bind(migration.id as i64)
+            .bind(checksum(sql_code))
+            .execute(&mut *conn)
+            .await?;
+        }
+
+        debug!("Applied file {:?}", &migration.file);
+        Ok(())
+    }
+}
+
+async fn set_last_migration_applied(conn: &mut AnyConnection, migration_id: u64) -> Result<()> {
+    sqlx::query(&Backend::of(conn).sql("UPDATE migren_data SET last_migration_applied = $1"))
+        .bind(migration_id as i64)
+        .execute(conn)
+        .await?;
+    Ok(())
+}
+
+async fn set_in_progress(
+    conn: &mut AnyConnection,
+    migration_id: u64,
+    direction: Direction,
+) -> Result<()> {
+    clear_in_progress(conn).await?;
+    sqlx::query(&Backend::of(conn).sql(
+        "INSERT INTO migren_in_progress (migration_id, direction, started_at) VALUES ($1, $2, $3)",
+    ))
+    .bind(migration_id as i64)
+    .bind(direction.as_str())
+    .bind(timestamp())
+    .execute(conn)
+    .await?;
+    Ok(())
+}
+
+async fn clear_in_progress(conn: &mut AnyConnection) -> Result<()> {
+    sqlx::query("DELETE FROM migren_in_progress")
+        .execute(conn)
+        .await?;
+    Ok(())
+}
+
+/// Fails if files of applied migrations were changed, unless drift is allowed.
+/// Migrations from `ignored` are not checked
+async fn check_drift(
+    conn: &mut AnyConnection,
+    migrations_data: &MigrationsData,
+    allow_drift: bool,
+    ignored: &[u64],
+) -> Result<()> {
+    let drift: Vec<MigrationDrift> = conn
+        .drift(migrations_data)
+        .await?
+        .into_iter()
+        .filter(|drift| !ignored.contains(&drift.id))
+        .collect();
+
+    if !drift.is_empty() {
+        if !allow_drift {
+            return Err(MigrenError::MigrationDrift(drift));
+        }
+        warn!("Applied migration files were changed on disk: {drift:#?}");
+    }
+
+    Ok(())
+}
+
+/// Moves database between migrations. With `repeatables` changed repeatable files
+/// are applied after migration files. Caller is responsible for locking
+async fn migrate(
+    conn: &mut AnyConnection,
+    migrations_data: &MigrationsData,
+    migration_id: u64,
+    repeatables: bool,
+    options: &MigrateOptions,
+) -> Result<MigrationReport> {
+    check_drift(conn, migrations_data, options.allow_drift, &[]).await?;
+    let repeatables = match repeatables {
+        true => pending_repeatables(conn, &migrations_data.directory).await?,
+        false => Vec::new(),
+    };
+    move_database(conn, migrations_data, migration_id, repeatables, options).await
+}
+
+/// Applies repeatable files. Consecutive transactional files are applied in one transaction
+async fn apply_repeatables(
+    conn: &mut AnyConnection,
+    repeatables: &[RepeatableToApply],
+) -> Result<()> {
+    for group in repeatables.chunk_by(|a, b| a.is_transactional() && b.is_transactional()) {
+        if group[0].is_transactional() {
+            let mut tx = conn.begin().await?;
+            for repeatable in group {
+                apply_repeatable(&mut tx, repeatable).await?;
+            }
+            tx.commit().await?;
         } else {
-            self.build_migration_path_down(start, stop)
+            debug!("Applying {:?} outside of transaction", group[0].file);
+            apply_repeatable(conn, &group[0]).await?;
         }
     }
+
+    Ok(())
 }
 
-#[derive(sqlx::FromRow, Debug)]
+/// Moves database between migrations without checking drift, then applies `repeatables`
+async fn move_database(
+    conn: &mut AnyConnection,
+    migrations_data: &MigrationsData,
+    migration_id: u64,
+    repeatables: Vec<RepeatableToApply>,
+    options: &MigrateOptions,
+) -> Result<MigrationReport> {
+    let start_id = conn.last_migration_applied(migrations_data).await?;
+
+    if start_id == migration_id {
+        debug!("Database is already at migration {migration_id}");
+        if !options.dry_run {
+            apply_repeatables(conn, &repeatables).await?;
+        }
+        return Ok(MigrationReport {
+            from: start_id,
+            to: migration_id,
+            applied: Vec::new(),
+            dry_run: options.dry_run,
+            repeatables,
+            backend: Backend::of(conn),
+        });
+    }
+
+    let migration_path = migrations_data.build_migration_path(start_id, migration_id)?;
+
+    if options.dry_run {
+        debug!("Dry run. Skipping execution of {migration_path:#?}");
+        return Ok(MigrationReport {
+            from: start_id,
+            to: migration_id,
+            applied: migration_path,
+            dry_run: true,
+            repeatables,
+            backend: Backend::of(conn),
+        });
+    }
+
+    debug!("{migration_path:#?}");
+
+    if let Some(in_progress) = conn.in_progress().await? {
+        let retried = migration_path.iter().any(|migration| {
+            migration.id == in_progress.migration_id as u64
+                && migration.direction.as_str() == in_progress.direction
+        });
+        warn!(
+            "Non-transactional migration {} ({}) started at {} did not finish. {}",
+            in_progress.migration_id,
+            in_progress.direction,
+            in_progress.started_at,
+            match retried {
+                true => "Running its file again from the start",
+                false => "Leaving it, check database state manually",
+            }
+        );
+    }
+
+    let mut files = Vec::with_capacity(migration_path.len());
+    for migration in migration_path.iter() {
+        files.push((migration, std::fs::read_to_string(&migration.file)?));
+    }
+
+    let recorder = HistoryRecorder {
+        migrations_data,
+        os_user: os_user(),
+        hostname: hostname(),
+    };
+
+    // Consecutive transactional migrations are applied in one transaction.
+    // Non-transactional ones are applied alone, with progress saved right after them.
+    // Transactional repeatable files join transaction of the last migrations
+    let groups: Vec<_> = files
+        .chunk_by(|(_, a), (_, b)| is_transactional(a) && is_transactional(b))
+        .collect();
+    let mut repeatables_applied = false;
+    for (group_index, group) in groups.iter().enumerate() {
+        let (last_migration, first_sql_code) = (group.last().unwrap().0, &group[0].1);
+        let position = if group_index == groups.len() - 1 {
+            migration_id
+        } else {
+            migrations_data.position_after(last_migration.id, last_migration.direction)
+        };
+
+        if is_transactional(first_sql_code) {
+            let mut tx = conn.begin().await?;
+            debug!("Begin transaction...");
+
+            for (migration, sql_code) in group.iter() {
+                recorder.apply(&mut tx, migration, sql_code).await?;
+            }
+            set_last_migration_applied(&mut tx, position).await?;
+
+            if group_index == groups.len() - 1
+                && repeatables
+                    .iter()
+                    .all(|repeatable| repeatable.is_transactional())
+            {
+                for repeatable in &repeatables {
+                    apply_repeatable(&mut tx, repeatable).await?;
+                }
+                repeatables_applied = true;
+            }
+
+            tx.commit().await?;
+            debug!("Transaction completed");
+        } else {
+            let (migration, sql_code) = &group[0];
+            debug!("Applying {:?} outside of transaction", migration.file);
+
+            set_in_progress(conn, migration.id, migration.direction).await?;
+            recorder.apply(conn, migration, sql_code).await?;
+            set_last_migration_applied(conn, position).await?;
+            clear_in_progress(conn).await?;
+        }
+    }
+    // Database moved past or before interrupted migration, so it is not in progress anymore
+    clear_in_progress(conn).await?;
+    if !repeatables_applied {
+        apply_repeatables(conn, &repeatables).await?;
+    }
+
+    Ok(MigrationReport {
+        from: start_id,
+        to: migration_id,
+        applied: migration_path,
+        dry_run: false,
+        repeatables,
+        backend: Backend::of(conn),
+    })
+}
+
+/// Replaces saved checksums with checksums of files on disk for every applied migration.
+/// Caller is responsible for locking
+async fn accept_checksums(
+    conn: &mut AnyConnection,
+    migrations_data: &MigrationsData,
+) -> Result<Vec<DatabaseMigrationChecksum>> {
+    let last_migration_applied = conn.last_migration_applied(migrations_data).await?;
+    let applied = migrations_data.applied_migrations(last_migration_applied)?;
+
+    let mut tx = conn.begin().await?;
+    sqlx::query("DELETE FROM migren_checksums")
+        .execute(&mut *tx)
+        .await?;
+
+    let mut accepted = Vec::with_capacity(applied.len());
+    for migration in applied {
+        assert_migration_files_exists(&migrations_data.directory, migration)?;
+        let checksum = file_checksum(&migrations_data.up_file(migration))?;
+        debug!(
+            "Accepting checksum {checksum} for migration {}",
+            migration.id
+        );
+
+        sqlx::query(
+            &Backend::of(&tx)
+                .sql("INSERT INTO migren_checksums (migration_id, checksum) VALUES ($1, $2)"),
+        )
+        .bind(migration.id as i64)
+        .bind(&checksum)
+        .execute(&mut *tx)
+        .await?;
+        accepted.push(DatabaseMigrationChecksum {
+            migration_id: migration.id as i64,
+            checksum,
+        });
+    }
+
+    tx.commit().await?;
+    Ok(accepted)
+}
+
+/// Records database as being at migration without executing any files.
+/// Caller is responsible for locking
+async fn baseline(
+    conn: &mut AnyConnection,
+    migrations_data: &MigrationsData,
+    migration_id: u64,
+) -> Result<()> {
+    let position = conn.last_migration_applied(migrations_data).await?;
+    if position != 0 {
+        return Err(MigrenError::AlreadyMigrated(position));
+    }
+    if migrations_data.migration_by_id(migration_id).is_none() {
+        return Err(MigrenError::MigrationPathInvalid {
+            from: 0,
+            to: migration_id,
+            comment: "to migration does not exists".to_string(),
+        });
+    }
+
+    let (os_user, hostname) = (os_user(), hostname());
+    let mut tx = conn.begin().await?;
+    for migration in migrations_data.applied_migrations(migration_id)? {
+        assert_migration_files_exists(&migrations_data.directory, migration)?;
+        let checksum = file_checksum(&migrations_data.up_file(migration))?;
+        let now = timestamp();
+
+        insert_history_entry(
+            &mut tx,
+            &DatabaseHistoryEntry {
+                migration_id: migration.id as i64,
+                name: migration.name.clone(),
+                direction: BASELINE_DIRECTION.to_string(),
+                checksum: checksum.clone(),
+                started_at: now.clone(),
+                finished_at: now,
+                duration_ms: 0,
+                os_user: os_user.clone(),
+                hostname: hostname.clone(),
+                migren_version: env!("CARGO_PKG_VERSION").to_string(),
+            },
+        )
+        .await?;
+
+        sqlx::query(
+            &Backend::of(&tx)
+                .sql("INSERT INTO migren_checksums (migration_id, checksum) VALUES ($1, $2)"),
+        )
+        .bind(migration.id as i64)
+        .bind(checksum)
+        .execute(&mut *tx)
+        .await?;
+        debug!("Recorded migration {} as applied", migration.id);
+    }
+    set_last_migration_applied(&mut tx, migration_id).await?;
+    tx.commit().await?;
+
+    Ok(())
+}
+
+/// Moves database `steps` migrations from where it is. Moves to last migration if `steps`
+/// is not set for up direction. Caller is responsible for locking
+async fn migrate_steps(
+    conn: &mut AnyConnection,
+    migrations_data: &MigrationsData,
+    direction: Direction,
+    steps: Option<u64>,
+    options: &MigrateOptions,
+) -> Result<MigrationReport> {
+    let start_id = conn.last_migration_applied(migrations_data).await?;
+    let migration_id = match (direction, steps) {
+        (Direction::Up, Some(steps)) => migrations_data.steps_forward(start_id, steps)?,
+        (Direction::Up, None) => migrations_data.migrations_counter,
+        (Direction::Down, steps) => migrations_data.steps_back(start_id, steps.unwrap_or(1))?,
+    };
+
+    migrate(conn, migrations_data, migration_id, false, options).await
+}
+
+/// Reverts last `steps` migrations and applies them back. Caller is responsible for locking
+async fn redo(
+    conn: &mut AnyConnection,
+    migrations_data: &MigrationsData,
+    steps: u64,
+    check_schema: bool,
+    options: &MigrateOptions,
+) -> Result<RedoReport> {
+    let start_id = conn.last_migration_applied(migrations_data).await?;
+    let target_id = migrations_data.steps_back(start_id, steps)?;
+    let redone: Vec<u64> = migrations_data
+        .build_migration_path(start_id, target_id)?
+        .iter()
+        .map(|migration| migration.id)
+        .collect();
+
+    // Redone migrations are usually edited, so only drift of other migrations matters
+    check_drift(conn, migrations_data, options.allow_drift, &redone).await?;
+
+    if options.dry_run {
+        return Ok(RedoReport {
+            down: move_database(conn, migrations_data, target_id, Vec::new(), options).await?,
+            up: MigrationReport {
+                from: target_id,
+                to: start_id,
+                applied: migrations_data.build_migration_path(target_id, start_id)?,
+                dry_run: true,
+                repeatables: Vec::new(),
+                backend: Backend::of(conn),
+            },
+        });
+    }
+
+    let down = move_database(conn, migrations_data, target_id, Vec::new(), options).await?;
+    assert_position(conn, migrations_data, target_id).await?;
+    let schema_before = match check_schema {
+        true => Some(schema_snapshot(conn).await?),
+        false => None,
+    };
+
+    let up = move_database(conn, migrations_data, start_id, Vec::new(), options).await?;
+    assert_position(conn, migrations_data, start_id).await?;
+
+    // Reverting once more shows if down files restore schema applied up files started from
+    if let Some(schema_before) = schema_before {
+        move_database(conn, migrations_data, target_id, Vec::new(), options).await?;
+        let schema_after = schema_snapshot(conn).await?;
+
+        let changes: Vec<String> = diff_lines(&schema_before, &schema_after)
+            .into_iter()
+            .filter_map(|line| match line {
+                DiffLine::Same(_) => None,
+                DiffLine::Removed(line) => Some(format!("- {line}")),
+                DiffLine::Added(line) => Some(format!("+ {line}")),
+            })
+            .collect();
+        if !changes.is_empty() {
+            return Err(MigrenError::RedoStateMismatch(format!(
+                "Schema differs after reverting migrations {redone:?}:\n{}",
+                changes.join("\n")
+            )));
+        }
+
+        move_database(conn, migrations_data, start_id, Vec::new(), options).await?;
+    }
+
+    Ok(RedoReport { down, up })
+}
+
+/// Fails if database is not at `expected` migration
+async fn assert_position(
+    conn: &mut AnyConnection,
+    migrations_data: &MigrationsData,
+    expected: u64,
+) -> Result<()> {
+    let position = conn.last_migration_applied(migrations_data).await?;
+    if position != expected {
+        return Err(MigrenError::RedoStateMismatch(format!(
+            "Database is at migration {position} instead of {expected}"
+        )));
+    }
+
+    Ok(())
+}
+
+/// Non-transactional migration which was started, but did not finish
+#[derive(sqlx::FromRow, Serialize, Debug, Clone)]
+pub struct DatabaseMigrationInProgress {
+    pub migration_id: i64,
+    /// `up` or `down`
+    pub direction: String,
+    pub started_at: String,
+}
+
+#[derive(sqlx::FromRow, Serialize, Debug)]
+pub struct DatabaseMigrationChecksum {
+    pub migration_id: i64,
+    pub checksum: String,
+}
+
+#[derive(sqlx::FromRow, Serialize, Debug)]
 pub struct DatabaseMigrenData {
     pub migren_version: String,
-    pub last_migration_applied: i32,
+    pub last_migration_applied: i64,
 }
 
 impl Default for DatabaseMigrenData {
@@ -250,27 +1340,79 @@ impl Default for DatabaseMigrenData {
     }
 }
 
+#[allow(async_fn_in_trait)]
 pub trait DatabaseMigrationer {
+    /// Data from migren_data table. Default one if database is not initialized
     async fn migren_data(&mut self) -> Result<DatabaseMigrenData>;
     async fn set_migren_data(&mut self, data: DatabaseMigrenData) -> Result<()>;
-    async fn to(&mut self, migrations_data: MigrationsData, migration_id: u32) -> Result<()>;
+    /// Migration database is at. Derived from migren_history, falls back to migren_data
+    /// for databases migrated before history was introduced
+    async fn last_migration_applied(&mut self, migrations_data: &MigrationsData) -> Result<u64>;
+    async fn history(&mut self) -> Result<Vec<DatabaseHistoryEntry>>;
+    /// Non-transactional migration which failed halfway, if any
+    async fn in_progress(&mut self) -> Result<Option<DatabaseMigrationInProgress>>;
+    async fn checksums(&mut self) -> Result<Vec<DatabaseMigrationChecksum>>;
+    /// Compares saved checksums of applied migrations with files on disk
+    async fn drift(&mut self, migrations_data: &MigrationsData) -> Result<Vec<MigrationDrift>>;
+    /// Saves checksums of files on disk for every applied migration. Returns saved checksums
+    async fn accept_checksums(
+        &mut self,
+        migrations_data: &MigrationsData,
+        options: &MigrateOptions,
+    ) -> Result<Vec<DatabaseMigrationChecksum>>;
+    async fn to(
+        &mut self,
+        migrations_data: &MigrationsData,
+        migration_id: u64,
+        options: &MigrateOptions,
+    ) -> Result<MigrationReport>;
+    /// Applies `steps` next migrations. Moves to last migration if `steps` is not set
+    async fn up(
+        &mut self,
+        migrations_data: &MigrationsData,
+        steps: Option<u64>,
+        options: &MigrateOptions,
+    ) -> Result<MigrationReport>;
+    /// Records database which was not migrated yet as being at migration,
+    /// without executing any files. For databases whose schema already exists
+    async fn baseline(
+        &mut self,
+        migrations_data: &MigrationsData,
+        migration_id: u64,
+        options: &MigrateOptions,
+    ) -> Result<()>;
+    /// Reverts `steps` last applied migrations
+    async fn down(
+        &mut self,
+        migrations_data: &MigrationsData,
+        steps: u64,
+        options: &MigrateOptions,
+    ) -> Result<MigrationReport>;
+    /// Reverts last `steps` migrations and applies them back. With `check_schema`
+    /// checks that down files restore schema up files started from
+    async fn redo(
+        &mut self,
+        migrations_data: &MigrationsData,
+        steps: u64,
+        check_schema: bool,
+        options: &MigrateOptions,
+    ) -> Result<RedoReport>;
     async fn exec(&mut self, sql_query: &str)
     -> Result<<sqlx::Any as sqlx::Database>::QueryResult>;
 }
 
 impl DatabaseMigrationer for sqlx::AnyConnection {
     async fn migren_data(&mut self) -> Result<DatabaseMigrenData> {
-        let mut migren_info =
-            sqlx::query_as::<_, DatabaseMigrenData>("SELECT * FROM migren_data LIMIT 1")
-                .fetch_all(&mut *self)
-                .await?;
-
-        if migren_info.len() == 0 {
-            self.set_migren_data(DatabaseMigrenData::default()).await?;
-            Ok(DatabaseMigrenData::default())
-        } else {
-            Ok(migren_info.pop().unwrap())
+        if !is_initialized(self).await? {
+            return Ok(DatabaseMigrenData::default());
         }
+
+        Ok(
+            sqlx::query_as::<_, DatabaseMigrenData>("SELECT * FROM migren_data LIMIT 1")
+                .fetch_optional(&mut *self)
+                .await?
+                .unwrap_or_default(),
+        )
     }
 
     async fn set_migren_data(&mut self, data: DatabaseMigrenData) -> Result<()> {
@@ -280,9 +1422,9 @@ impl DatabaseMigrationer for sqlx::AnyConnection {
             .await?;
         debug!("Removed all rows from migren_data");
 
-        sqlx::query(
+        sqlx::query(&Backend::of(self).sql(
             "INSERT INTO migren_data (migren_version, last_migration_applied) VALUES ($1, $2);",
-        )
+        ))
         .bind(data.migren_version)
         .bind(data.last_migration_applied)
         .execute(&mut *self)
@@ -292,69 +1434,232 @@ impl DatabaseMigrationer for sqlx::AnyConnection {
         Ok(())
     }
 
-    async fn to(&mut self, migrations_data: MigrationsData, migration_id: u32) -> Result<()> {
+    async fn last_migration_applied(&mut self, migrations_data: &MigrationsData) -> Result<u64> {
         let migren_data = self.migren_data().await?;
+        let history = history(self).await?;
+        if history.is_empty() {
+            return Ok(migren_data.last_migration_applied as u64);
+        }
 
-        if migren_data.last_migration_applied == migration_id as i32 {
-            info!("Database is already at migration {migration_id}");
-            return Ok(());
+        let mut applied = HashSet::new();
+        for entry in history {
+            match entry.direction() {
+                Direction::Up => applied.insert(entry.migration_id as u64),
+                Direction::Down => applied.remove(&(entry.migration_id as u64)),
+            };
         }
 
-        let start_id = migren_data.last_migration_applied as u32;
-        let migration_path = migrations_data.build_migration_path(start_id, migration_id)?;
+        // Databases which applied every squashed migration are at migration they were squashed into
+        for squash in &migrations_data.squashes {
+            if squash
+                .migrations
+                .iter()
+                .all(|migration| applied.contains(&migration.id))
+            {
+                for migration in &squash.migrations {
+                    applied.remove(&migration.id);
+                }
+                applied.insert(squash.id);
+            }
+        }
 
-        let mut tx = self.begin().await?;
-        debug!("Begin transaction...");
+        // History can be incomplete for databases migrated before it was introduced
+        Ok(migrations_data
+            .position_of(&applied)
+            .unwrap_or(migren_data.last_migration_applied as u64))
+    }
 
-        debug!("{migration_path:#?}");
+    async fn history(&mut self) -> Result<Vec<DatabaseHistoryEntry>> {
+        history(self).await
+    }
 
-        for migration in migration_path.into_iter() {
-            let sql_code = std::fs::read_to_string(&migration.file)?;
+    async fn in_progress(&mut self) -> Result<Option<DatabaseMigrationInProgress>> {
+        if !table_exists(self, "migren_in_progress").await? {
+            return Ok(None);
+        }
 
-            // TODO: MIG-23 - move usage of this derictive into distinct place + maybe add a couple
-            // extra derictives.
-            let mut statement_buffer = String::new();
-            for line in sql_code.lines() {
-                if let Some(derictive) = SqlDirective::match_str(line) {
-                    debug!("Found directive: {derictive:?}");
-                    match derictive {
-                        SqlDirective::Split => {
-                            sqlx::query(&statement_buffer).execute(&mut *tx).await?;
-                            statement_buffer = String::new();
-                        }
-                    }
-                }
+        Ok(sqlx::query_as::<_, DatabaseMigrationInProgress>(
+            "SELECT migration_id, direction, started_at FROM migren_in_progress LIMIT 1",
+        )
+        .fetch_optional(&mut *self)
+        .await?)
+    }
 
-                statement_buffer.push_str(line);
-                statement_buffer.push_str("\n");
+    async fn checksums(&mut self) -> Result<Vec<DatabaseMigrationChecksum>> {
+        if !table_exists(self, "migren_checksums").await? {
+            return Ok(Vec::new());
+        }
+
+        Ok(sqlx::query_as::<_, DatabaseMigrationChecksum>(
+            "SELECT migration_id, checksum FROM migren_checksums ORDER BY migration_id",
+        )
+        .fetch_all(&mut *self)
+        .await?)
+    }
+
+    async fn drift(&mut self, migrations_data: &MigrationsData) -> Result<Vec<MigrationDrift>> {
+        let mut drift = Vec::new();
+
+        for saved in self.checksums().await? {
+            // Databases migrated before squash have checksums of squashed files
+            let squashed = migrations_data.squashes.iter().any(|squash| {
+                squash.migrations.iter().any(|migration| {
+                    migration.id == saved.migration_id as u64
+                        && migration.checksum == saved.checksum
+                })
+            });
+            if squashed {
+                continue;
             }
-            
-            sqlx::query(&statement_buffer).execute(&mut *tx).await?;
 
-            let semicolons_count = sql_code
-                .as_bytes()
-                .iter()
-                .filter(|x| **x == ';' as u8)
-                .count();
-            if semicolons_count > 1 {
-                warn!(
-                    "Multiple semicolons found in the same query! Suggest using only one command in file, because it can lead to some problems... Semicolons count: {semicolons_count}"
-                );
+            let migration = migrations_data.migration_by_id(saved.migration_id as u64);
+            let file = migration.map(|migration| migrations_data.up_file(migration));
+            let current_checksum = match &file {
+                Some(file) if std::fs::exists(file)? => Some(file_checksum(file)?),
+                _ => None,
+            };
+
+            if current_checksum.as_ref() != Some(&saved.checksum) {
+                drift.push(MigrationDrift {
+                    id: saved.migration_id as u64,
+                    file,
+                    applied_checksum: saved.checksum,
+                    current_checksum,
+                });
             }
+        }
 
-            info!("Applied file {:?}", &migration.file);
+        Ok(drift)
+    }
+
+    async fn accept_checksums(
+        &mut self,
+        migrations_data: &MigrationsData,
+        options: &MigrateOptions,
+    ) -> Result<Vec<DatabaseMigrationChecksum>> {
+        lock(self, options.lock_timeout).await?;
+        let result = async {
+            init(self, options.production).await?;
+            accept_checksums(self, migrations_data).await
         }
+        .await;
+        let unlocked = unlock(self).await;
 
-        sqlx::query("UPDATE migren_data SET last_migration_applied = $1")
-            .bind(migration_id as i32)
-            .execute(&mut *tx)
-            .await?;
+        let accepted = result?;
+        unlocked?;
+        Ok(accepted)
+    }
 
-        tx.commit().await?;
+    async fn to(
+        &mut self,
+        migrations_data: &MigrationsData,
+        migration_id: u64,
+        options: &MigrateOptions,
+    ) -> Result<MigrationReport> {
+        if options.dry_run {
+            return migrate(self, migrations_data, migration_id, true, options).await;
+        }
 
-        info!("Transaction completed");
+        lock(self, options.lock_timeout).await?;
+        let result = async {
+            init(self, options.production).await?;
+            migrate(self, migrations_data, migration_id, true, options).await
+        }
+        .await;
+        let unlocked = unlock(self).await;
 
-        Ok(())
+        let report = result?;
+        unlocked?;
+        Ok(report)
+    }
+
+    async fn up(
+        &mut self,
+        migrations_data: &MigrationsData,
+        steps: Option<u64>,
+        options: &MigrateOptions,
+    ) -> Result<MigrationReport> {
+        if options.dry_run {
+            return migrate_steps(self, migrations_data, Direction::Up, steps, options).await;
+        }
+
+        lock(self, options.lock_timeout).await?;
+        let result = async {
+            init(self, options.production).await?;
+            migrate_steps(self, migrations_data, Direction::Up, steps, options).await
+        }
+        .await;
+        let unlocked = unlock(self).await;
+
+        let report = result?;
+        unlocked?;
+        Ok(report)
+    }
+
+    async fn baseline(
+        &mut self,
+        migrations_data: &MigrationsData,
+        migration_id: u64,
+        options: &MigrateOptions,
+    ) -> Result<()> {
+        lock(self, options.lock_timeout).await?;
+        let result = async {
+            init(self, options.production).await?;
+            baseline(self, migrations_data, migration_id).await
+        }
+        .await;
+        let unlocked = unlock(self).await;
+
+        result?;
+        unlocked
+    }
+
+    async fn down(
+        &mut self,
+        migrations_data: &MigrationsData,
+        steps: u64,
+        options: &MigrateOptions,
+    ) -> Result<MigrationReport> {
+        let steps = Some(steps);
+        if options.dry_run {
+            return migrate_steps(self, migrations_data, Direction::Down, steps, options).await;
+        }
+
+        lock(self, options.lock_timeout).await?;
+        let result = async {
+            init(self, options.production).await?;
+            migrate_steps(self, migrations_data, Direction::Down, steps, options).await
+        }
+        .await;
+        let unlocked = unlock(self).await;
+
+        let report = result?;
+        unlocked?;
+        Ok(report)
+    }
+
+    async fn redo(
+        &mut self,
+        migrations_data: &MigrationsData,
+        steps: u64,
+        check_schema: bool,
+        options: &MigrateOptions,
+    ) -> Result<RedoReport> {
+        if options.dry_run {
+            return redo(self, migrations_data, steps, check_schema, options).await;
+        }
+
+        lock(self, options.lock_timeout).await?;
+        let result = async {
+            init(self, options.production).await?;
+            redo(self, migrations_data, steps, check_schema, options).await
+        }
+        .await;
+        let unlocked = unlock(self).await;
+
+        let report = result?;
+        unlocked?;
+        Ok(report)
     }
 
     async fn exec(
@@ -364,3 +1669,655 @@ impl DatabaseMigrationer for sqlx::AnyConnection {
         Ok(sqlx::query(sql_query).execute(&mut *self).await?)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::{test_connection, test_migrations_data};
+
+    /// 0 -> 1 -> 2 -> 3
+    fn linear(test_name: &str) -> MigrationsData {
+        let mut data = test_migrations_data(test_name);
+        for name in ["a", "b", "c"] {
+            data.new_migration(name).unwrap();
+        }
+        data
+    }
+
+    /// 0 -> 1 -> 2 and 1 -> 3
+    fn diverged(test_name: &str) -> MigrationsData {
+        let mut data = test_migrations_data(test_name);
+        data.new_migration("a").unwrap();
+        data.new_migration("b").unwrap();
+        data.add_migration("c", vec![1]).unwrap();
+        data
+    }
+
+    /// 0 -> 1 -> 2 -> 4 and 1 -> 3 -> 4
+    fn merged(test_name: &str) -> MigrationsData {
+        let mut data = diverged(test_name);
+        data.merge_heads("merge").unwrap();
+        data
+    }
+
+    fn path(data: &MigrationsData, from: u64, to: u64) -> Vec<(u64, Direction)> {
+        data.build_migration_path(from, to)
+            .unwrap()
+            .into_iter()
+            .map(|migration| (migration.id, migration.direction))
+            .collect()
+    }
+
+    fn ids(ids: &[u64]) -> HashSet<u64> {
+        ids.iter().copied().collect()
+    }
+
+    fn write_migration(data: &MigrationsData, id: u64, up_sql: &str, down_sql: &str) {
+        let migration = data.migration_by_id(id).unwrap();
+        std::fs::write(data.up_file(migration), up_sql).unwrap();
+        std::fs::write(data.down_file(migration), down_sql).unwrap();
+    }
+
+    #[tokio::test]
+    async fn clears_interrupted_migration_after_moving_database() {
+        let mut data = test_migrations_data("clears_interrupted_migration_after_moving_database");
+        data.new_migration("a").unwrap();
+        data.new_migration("b").unwrap();
+        write_migration(&data, 1, "CREATE TABLE a (id INTEGER);", "DROP TABLE a;");
+        write_migration(
+            &data,
+            2,
+            "-- migren:no-transaction\nCREATE TABLE b (id INTEGER);\nSELECT * FROM missing;",
+            "DROP TABLE b;",
+        );
+        let mut conn = test_connection().await;
+        let options = MigrateOptions::default();
+
+        assert!(conn.to(&data, 2, &options).await.is_err());
+        assert_eq!(conn.last_migration_applied(&data).await.unwrap(), 1);
+        let in_progress = conn.in_progress().await.unwrap().unwrap();
+        assert_eq!(
+            (in_progress.migration_id, in_progress.direction.as_str()),
+            (2, "up")
+        );
+
+        // Failed again, file is retried from the start
+        assert!(conn.to(&data, 2, &options).await.is_err());
+        assert!(conn.in_progress().await.unwrap().is_some());
+
+        write_migration(
+            &data,
+            2,
+            "-- migren:no-transaction\nCREATE TABLE IF NOT EXISTS b (id INTEGER);",
+            "DROP TABLE b;",
+        );
+        conn.to(&data, 2, &options).await.unwrap();
+        assert!(conn.in_progress().await.unwrap().is_none());
+        assert_eq!(conn.last_migration_applied(&data).await.unwrap(), 2);
+    }
+
+    #[tokio::test]
+    async fn clears_interrupted_migration_after_moving_before_it() {
+        let mut data = test_migrations_data("clears_interrupted_migration_after_moving_before_it");
+        data.new_migration("a").unwrap();
+        data.new_migration("b").unwrap();
+        write_migration(&data, 1, "CREATE TABLE a (id INTEGER);", "DROP TABLE a;");
+        write_migration(
+            &data,
+            2,
+            "-- migren:no-transaction\nSELECT * FROM missing;",
+            "SELECT 1;",
+        );
+        let mut conn = test_connection().await;
+        let options = MigrateOptions::default();
+
+        assert!(conn.to(&data, 2, &options).await.is_err());
+        assert!(conn.in_progress().await.unwrap().is_some());
+
+        conn.to(&data, 0, &options).await.unwrap();
+        assert!(conn.in_progress().await.unwrap().is_none());
+        assert_eq!(conn.last_migration_applied(&data).await.unwrap(), 0);
+    }
+
+    #[test]
+    fn links_new_migrations() {
+        let data = merged("links_new_migrations");
+        assert_eq!(data.heads(), [4]);
+        assert_eq!(data.migration_by_id(1).unwrap().next_migration_ids, [2, 3]);
+        assert_eq!(data.migration_by_id(4).unwrap().prev_migration_ids, [2, 3]);
+        assert_eq!(diverged("links_new_migrations_diverged").heads(), [2, 3]);
+    }
+
+    #[test]
+    fn finds_ancestors() {
+        let data = merged("finds_ancestors");
+        assert_eq!(data.ancestors(0).unwrap(), ids(&[0]));
+        assert_eq!(data.ancestors(2).unwrap(), ids(&[0, 1, 2]));
+        assert_eq!(data.ancestors(3).unwrap(), ids(&[0, 1, 3]));
+        assert_eq!(data.ancestors(4).unwrap(), ids(&[0, 1, 2, 3, 4]));
+    }
+
+    #[test]
+    fn orders_migrations_after_previous_ones() {
+        let mut data = merged("orders_migrations_after_previous_ones");
+        // Branch migration with smaller id than the other branch
+        data.add_migration("d", vec![3]).unwrap();
+        data.add_migration("e", vec![4, 5]).unwrap();
+
+        assert_eq!(
+            data.topological_order(&data.ancestors(6).unwrap()).unwrap(),
+            [0, 1, 2, 3, 4, 5, 6]
+        );
+        assert_eq!(data.topological_order(&ids(&[3, 5, 1])).unwrap(), [1, 3, 5]);
+    }
+
+    #[test]
+    fn fails_on_circular_migrations() {
+        let mut data = linear("fails_on_circular_migrations");
+        data.migration_by_id_mut(1).unwrap().prev_migration_ids = vec![0, 3];
+        data.migration_by_id_mut(3).unwrap().next_migration_ids = vec![1];
+
+        assert!(matches!(
+            data.topological_order(&ids(&[1, 2, 3])),
+            Err(MigrenError::MigrationPathInvalid { .. })
+        ));
+    }
+
+    #[test]
+    fn builds_linear_path() {
+        let data = linear("builds_linear_path");
+        assert_eq!(
+            path(&data, 0, 3),
+            [(1, Direction::Up), (2, Direction::Up), (3, Direction::Up)]
+        );
+        assert_eq!(
+            path(&data, 3, 1),
+            [(3, Direction::Down), (2, Direction::Down)]
+        );
+        assert_eq!(path(&data, 2, 2), []);
+    }
+
+    #[test]
+    fn builds_path_between_diverged_branches() {
+        let data = diverged("builds_path_between_diverged_branches");
+        assert_eq!(
+            path(&data, 2, 3),
+            [(2, Direction::Down), (3, Direction::Up)]
+        );
+        assert_eq!(
+            path(&data, 3, 2),
+            [(3, Direction::Down), (2, Direction::Up)]
+        );
+        assert_eq!(path(&data, 0, 3), [(1, Direction::Up), (3, Direction::Up)]);
+    }
+
+    #[test]
+    fn builds_path_through_merge() {
+        let data = merged("builds_path_through_merge");
+        assert_eq!(
+            path(&data, 0, 4),
+            [
+                (1, Direction::Up),
+                (2, Direction::Up),
+                (3, Direction::Up),
+                (4, Direction::Up)
+            ]
+        );
+        assert_eq!(path(&data, 2, 4), [(3, Direction::Up), (4, Direction::Up)]);
+        assert_eq!(
+            path(&data, 4, 1),
+            [
+                (4, Direction::Down),
+                (3, Direction::Down),
+                (2, Direction::Down)
+            ]
+        );
+        assert_eq!(
+            path(&data, 4, 2),
+            [(4, Direction::Down), (3, Direction::Down)]
+        );
+    }
+
+    #[test]
+    fn builds_path_with_files_in_directory() {
+        let data = linear("builds_path_with_files_in_directory");
+        let path = data.build_migration_path(0, 1).unwrap();
+        assert_eq!(path[0].file, data.directory.join("1_a_up.sql"));
+
+        std::fs::remove_file(data.directory.join("2_b_down.sql")).unwrap();
+        assert!(matches!(
+            data.build_migration_path(3, 0),
+            Err(MigrenError::MigrationFilesDoesNotExsists(_))
+        ));
+        assert!(matches!(
+            data.build_migration_path(0, 10),
+            Err(MigrenError::MigrationPathInvalid { .. })
+        ));
+    }
+
+    #[test]
+    fn finds_position_of_applied_migrations() {
+        let data = merged("finds_position_of_applied_migrations");
+        assert_eq!(data.position_of(&ids(&[1, 2])), Some(2));
+        assert_eq!(data.position_of(&ids(&[1, 2, 3, 4])), Some(4));
+        // Both branches applied without merge is not a single migration
+        assert_eq!(data.position_of(&ids(&[1, 2, 3])), None);
+        // Gap in applied migrations
+        assert_eq!(data.position_of(&ids(&[2])), None);
+        assert_eq!(data.position_of(&ids(&[])), None);
+    }
+
+    #[test]
+    fn lists_applied_migrations_in_order() {
+        let data = merged("lists_applied_migrations_in_order");
+        let applied: Vec<u64> = data
+            .applied_migrations(4)
+            .unwrap()
+            .into_iter()
+            .map(|migration| migration.id)
+            .collect();
+        assert_eq!(applied, [1, 2, 3, 4]);
+        assert!(data.applied_migrations(0).unwrap().is_empty());
+    }
+
+    #[test]
+    fn steps_forward_on_single_branch_only() {
+        let data = diverged("steps_forward_on_single_branch_only");
+        assert_eq!(data.steps_forward(0, 1).unwrap(), 1);
+        assert!(matches!(
+            data.steps_forward(1, 1),
+            Err(MigrenError::MigrationPathInvalid { .. })
+        ));
+        assert!(matches!(
+            data.steps_forward(2, 1),
+            Err(MigrenError::MigrationPathInvalid { .. })
+        ));
+    }
+
+    #[test]
+    fn steps_back_before_merge_only() {
+        let data = merged("steps_back_before_merge_only");
+        assert_eq!(data.steps_back(3, 1).unwrap(), 1);
+        assert_eq!(data.steps_back(2, 2).unwrap(), 0);
+        assert!(matches!(
+            data.steps_back(4, 1),
+            Err(MigrenError::MigrationPathInvalid { to: 4, .. })
+        ));
+        assert!(matches!(
+            data.steps_back(2, 3),
+            Err(MigrenError::MigrationPathInvalid { to: 0, .. })
+        ));
+    }
+
+    #[test]
+    fn reads_legacy_single_links() {
+        let data: MigrationData = serde_json::from_str(
+            r#"{
+                "files": {"up_migration_file": "2_b_up.sql", "down_migration_file": "2_b_down.sql"},
+                "name": "b",
+                "id": 2,
+                "prev_migration_id": 1,
+                "next_migration_id": null
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(data.prev_migration_ids, [1]);
+        assert!(data.next_migration_ids.is_empty());
+
+        let data: MigrationData = serde_json::from_str(
+            r#"{
+                "files": {"up_migration_file": "", "down_migration_file": ""},
+                "name": "initial",
+                "id": 0,
+                "prev_migration_id": null,
+                "next_migration_id": 1
+            }"#,
+        )
+        .unwrap();
+        assert!(data.prev_migration_ids.is_empty());
+        assert_eq!(data.next_migration_ids, [1]);
+    }
+
+    #[test]
+    fn reads_links_without_fields() {
+        let data: MigrationData = serde_json::from_str(
+            r#"{"files": {"up_migration_file": "", "down_migration_file": ""}, "name": "initial", "id": 0}"#,
+        )
+        .unwrap();
+        assert!(data.prev_migration_ids.is_empty());
+        assert!(data.next_migration_ids.is_empty());
+    }
+
+    /// 0 -> 1 -> 2 -> 3, every migration creating its table
+    fn linear_with_tables(test_name: &str) -> MigrationsData {
+        let data = linear(test_name);
+        for (id, table) in [(1, "a"), (2, "b"), (3, "c")] {
+            write_migration(
+                &data,
+                id,
+                &format!("CREATE TABLE {table} (id INTEGER);"),
+                &format!("DROP TABLE {table};"),
+            );
+        }
+        data
+    }
+
+    #[test]
+    fn squashes_files_of_range() {
+        let mut data = linear_with_tables("squashes_files_of_range");
+        data.squash(2, 3, "squashed").unwrap();
+
+        let directory = data.directory.clone();
+        assert_eq!(
+            std::fs::read_to_string(directory.join("3_squashed_up.sql")).unwrap(),
+            "-- migren:split 2 - b\nCREATE TABLE b (id INTEGER);\n\n-- migren:split 3 - c\nCREATE TABLE c (id INTEGER);\n"
+        );
+        assert_eq!(
+            std::fs::read_to_string(directory.join("3_squashed_down.sql")).unwrap(),
+            "-- migren:split 3 - c\nDROP TABLE c;\n\n-- migren:split 2 - b\nDROP TABLE b;\n"
+        );
+        for file in ["2_b_up.sql", "2_b_down.sql", "3_c_up.sql", "3_c_down.sql"] {
+            assert!(!directory.join(file).exists(), "{file}");
+        }
+        assert!(directory.join("1_a_up.sql").exists());
+
+        let saved = crate::util::load_migrations_data(&directory).unwrap();
+        let ids: Vec<u64> = saved
+            .migrations
+            .iter()
+            .map(|migration| migration.id)
+            .collect();
+        assert_eq!(ids, [0, 1, 3]);
+        assert_eq!(saved.migration_by_id(1).unwrap().next_migration_ids, [3]);
+        assert_eq!(saved.migration_by_id(3).unwrap().prev_migration_ids, [1]);
+        assert_eq!(saved.migrations_counter, 3);
+        let squashed: Vec<u64> = saved.squashes[0]
+            .migrations
+            .iter()
+            .map(|migration| migration.id)
+            .collect();
+        assert_eq!((saved.squashes[0].id, squashed), (3, vec![2, 3]));
+        assert_eq!(saved.squashed_into(2), Some(3));
+        assert_eq!(saved.squashed_into(3), None);
+    }
+
+    #[test]
+    fn keeps_file_boundaries_in_squash() {
+        let mut data = linear("keeps_file_boundaries_in_squash");
+        write_migration(
+            &data,
+            1,
+            "DELIMITER //\nCREATE PROCEDURE p() BEGIN SELECT 1; END//",
+            "DROP PROCEDURE p;",
+        );
+        write_migration(&data, 2, "SELECT 2", "SELECT 2;");
+        write_migration(&data, 3, "SELECT 3;", "SELECT 3;");
+        data.squash(1, 3, "squashed").unwrap();
+
+        let up_sql = std::fs::read_to_string(data.directory.join("3_squashed_up.sql")).unwrap();
+        let statements: Vec<String> = split_sql(&up_sql, Backend::MySql)
+            .into_iter()
+            .map(|statement| strip_comment_lines(&statement.sql))
+            .collect();
+        assert_eq!(
+            statements,
+            [
+                "CREATE PROCEDURE p() BEGIN SELECT 1; END",
+                "SELECT 2",
+                "SELECT 3"
+            ]
+        );
+    }
+
+    fn strip_comment_lines(sql: &str) -> String {
+        sql.lines()
+            .filter(|line| !line.starts_with("--"))
+            .collect::<Vec<_>>()
+            .join("\n")
+            .trim()
+            .to_string()
+    }
+
+    #[test]
+    fn refuses_to_squash_mixed_transactions() {
+        let mut data = linear_with_tables("refuses_to_squash_mixed_transactions");
+        write_migration(
+            &data,
+            2,
+            "-- migren:no-transaction\nCREATE INDEX CONCURRENTLY a_id ON a (id);",
+            "DROP INDEX a_id;",
+        );
+
+        assert!(matches!(
+            data.squash(1, 3, "squashed"),
+            Err(MigrenError::MigrationPathInvalid { .. })
+        ));
+        assert!(data.directory.join("2_b_up.sql").exists());
+        assert!(!data.directory.join("3_squashed_up.sql").exists());
+        assert_eq!(data.migrations.len(), 4);
+    }
+
+    #[tokio::test]
+    async fn keeps_position_of_database_after_squash() {
+        let test_name = "keeps_position_of_database_after_squash";
+        let mut data = linear_with_tables(test_name);
+        let options = MigrateOptions::default();
+        let mut at_end = test_connection().await;
+        at_end.to(&data, 3, &options).await.unwrap();
+        let mut before = test_connection().await;
+        before.to(&data, 1, &options).await.unwrap();
+        let mut inside = test_connection().await;
+        inside.to(&data, 2, &options).await.unwrap();
+
+        data.squash(2, 3, "squashed").unwrap();
+
+        assert_eq!(at_end.last_migration_applied(&data).await.unwrap(), 3);
+        assert!(at_end.drift(&data).await.unwrap().is_empty());
+        at_end.to(&data, 3, &options).await.unwrap();
+
+        before.to(&data, 3, &options).await.unwrap();
+        assert_eq!(before.last_migration_applied(&data).await.unwrap(), 3);
+
+        assert!(matches!(
+            inside.to(&data, 3, &options).await,
+            Err(MigrenError::SquashedMigration {
+                position: 2,
+                squashed_into: 3
+            })
+        ));
+    }
+
+    fn write_repeatable(data: &MigrationsData, name: &str, sql_code: &str) {
+        let directory = data.directory.join(crate::repeatable::REPEATABLE_DIR_NAME);
+        std::fs::create_dir_all(&directory).unwrap();
+        std::fs::write(directory.join(name), sql_code).unwrap();
+    }
+
+    fn repeatable_names(report: &MigrationReport) -> Vec<&str> {
+        report
+            .repeatables
+            .iter()
+            .map(|repeatable| repeatable.name.as_str())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn reapplies_changed_repeatables() {
+        let data = linear_with_tables("reapplies_changed_repeatables");
+        write_repeatable(
+            &data,
+            "1_a_view.sql",
+            "CREATE VIEW a_view AS SELECT id FROM a;",
+        );
+        write_repeatable(
+            &data,
+            "2_b_view.sql",
+            "CREATE VIEW b_view AS SELECT id FROM b;",
+        );
+        let mut conn = test_connection().await;
+        let options = MigrateOptions::default();
+
+        let report = conn.to(&data, 2, &options).await.unwrap();
+        assert_eq!(repeatable_names(&report), ["1_a_view.sql", "2_b_view.sql"]);
+
+        let report = conn.to(&data, 3, &options).await.unwrap();
+        assert!(report.repeatables.is_empty());
+
+        write_repeatable(
+            &data,
+            "1_a_view.sql",
+            "DROP VIEW IF EXISTS a_view;\nCREATE VIEW a_view AS SELECT id, id AS a_id FROM a;",
+        );
+        let report = conn.to(&data, 3, &options).await.unwrap();
+        assert_eq!(repeatable_names(&report), ["1_a_view.sql"]);
+        conn.exec("SELECT a_id FROM a_view").await.unwrap();
+
+        let applied = sqlx::query_scalar::<_, i64>("SELECT COUNT(*) FROM migren_repeatable")
+            .fetch_one(&mut conn)
+            .await
+            .unwrap();
+        assert_eq!(applied, 2);
+    }
+
+    #[tokio::test]
+    async fn rolls_back_last_migrations_with_failed_repeatable() {
+        let data = linear_with_tables("rolls_back_last_migrations_with_failed_repeatable");
+        write_repeatable(
+            &data,
+            "1_view.sql",
+            "CREATE VIEW c_view AS SELECT id FROM c;",
+        );
+        write_repeatable(&data, "2_broken.sql", "SELECT * FROM missing;");
+        let mut conn = test_connection().await;
+        let options = MigrateOptions::default();
+
+        assert!(conn.to(&data, 3, &options).await.is_err());
+
+        assert_eq!(conn.last_migration_applied(&data).await.unwrap(), 0);
+        for table in ["a", "b", "c"] {
+            assert!(
+                !crate::database::table_exists(&mut conn, table)
+                    .await
+                    .unwrap()
+            );
+        }
+        let pending = pending_repeatables(&mut conn, &data.directory)
+            .await
+            .unwrap();
+        assert_eq!(pending.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn applies_non_transactional_repeatable_after_migrations() {
+        let data = linear_with_tables("applies_non_transactional_repeatable_after_migrations");
+        write_repeatable(
+            &data,
+            "1_broken.sql",
+            "-- migren:no-transaction\nSELECT * FROM missing;",
+        );
+        let mut conn = test_connection().await;
+        let options = MigrateOptions::default();
+
+        assert!(conn.to(&data, 3, &options).await.is_err());
+
+        assert_eq!(conn.last_migration_applied(&data).await.unwrap(), 3);
+        assert!(crate::database::table_exists(&mut conn, "c").await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn refuses_to_migrate_with_drift() {
+        let data = linear_with_tables("refuses_to_migrate_with_drift");
+        let mut conn = test_connection().await;
+        let options = MigrateOptions::default();
+        conn.to(&data, 2, &options).await.unwrap();
+        assert!(conn.drift(&data).await.unwrap().is_empty());
+
+        write_migration(&data, 1, "CREATE TABLE a (id BIGINT);", "DROP TABLE a;");
+        let drift = conn.drift(&data).await.unwrap();
+        assert_eq!(drift.len(), 1);
+        assert_eq!(drift[0].id, 1);
+        assert!(drift[0].current_checksum.is_some());
+
+        assert!(matches!(
+            conn.to(&data, 3, &options).await,
+            Err(MigrenError::MigrationDrift(drift)) if drift.len() == 1
+        ));
+        assert_eq!(conn.last_migration_applied(&data).await.unwrap(), 2);
+
+        let allowed = MigrateOptions {
+            allow_drift: true,
+            ..MigrateOptions::default()
+        };
+        conn.to(&data, 3, &allowed).await.unwrap();
+        assert_eq!(conn.last_migration_applied(&data).await.unwrap(), 3);
+    }
+
+    #[tokio::test]
+    async fn accepts_checksums_of_changed_files() {
+        let data = linear_with_tables("accepts_checksums_of_changed_files");
+        let mut conn = test_connection().await;
+        let options = MigrateOptions::default();
+        conn.to(&data, 2, &options).await.unwrap();
+        write_migration(&data, 2, "CREATE TABLE b (id BIGINT);", "DROP TABLE b;");
+
+        let accepted = conn.accept_checksums(&data, &options).await.unwrap();
+        let ids: Vec<i64> = accepted
+            .iter()
+            .map(|checksum| checksum.migration_id)
+            .collect();
+        assert_eq!(ids, [1, 2]);
+        assert_eq!(conn.checksums().await.unwrap().len(), 2);
+        assert!(conn.drift(&data).await.unwrap().is_empty());
+        conn.to(&data, 3, &options).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn records_baseline_without_running_files() {
+        let data = linear_with_tables("records_baseline_without_running_files");
+        let mut conn = test_connection().await;
+        let options = MigrateOptions::default();
+
+        conn.baseline(&data, 2, &options).await.unwrap();
+
+        assert_eq!(conn.last_migration_applied(&data).await.unwrap(), 2);
+        assert!(!crate::database::table_exists(&mut conn, "a").await.unwrap());
+        let entries: Vec<(i64, String)> = conn
+            .history()
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|entry| (entry.migration_id, entry.direction))
+            .collect();
+        assert_eq!(
+            entries,
+            [
+                (1, BASELINE_DIRECTION.to_string()),
+                (2, BASELINE_DIRECTION.to_string())
+            ]
+        );
+        assert_eq!(conn.checksums().await.unwrap().len(), 2);
+        assert!(conn.drift(&data).await.unwrap().is_empty());
+
+        conn.to(&data, 3, &options).await.unwrap();
+        assert!(crate::database::table_exists(&mut conn, "c").await.unwrap());
+        assert!(!crate::database::table_exists(&mut conn, "b").await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn refuses_baseline_of_migrated_database() {
+        let data = linear_with_tables("refuses_baseline_of_migrated_database");
+        let mut conn = test_connection().await;
+        let options = MigrateOptions::default();
+
+        assert!(matches!(
+            conn.baseline(&data, 4, &options).await,
+            Err(MigrenError::MigrationPathInvalid { to: 4, .. })
+        ));
+        conn.to(&data, 1, &options).await.unwrap();
+        assert!(matches!(
+            conn.baseline(&data, 2, &options).await,
+            Err(MigrenError::AlreadyMigrated(1))
+        ));
+        assert_eq!(conn.last_migration_applied(&data).await.unwrap(), 1);
+    }
+}
@@ -0,0 +1,162 @@
+use chrono::{SecondsFormat, Utc};
+use serde::Serialize;
+use sqlx::AnyConnection;
+
+use crate::{database::table_exists, errors::Result, schema::Backend};
+
+/// Direction migration file was applied in
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Direction {
+    Up,
+    Down,
+}
+
+impl Direction {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Direction::Up => "up",
+            Direction::Down => "down",
+        }
+    }
+}
+
+/// Direction saved for migrations recorded by baseline without executing their files
+pub const BASELINE_DIRECTION: &str = "baseline";
+
+/// Row of migren_history table. One row per applied or reverted migration
+#[derive(sqlx::FromRow, Serialize, Debug, Clone)]
+pub struct DatabaseHistoryEntry {
+    pub migration_id: i64,
+    pub name: String,
+    /// `up`, `down` or `baseline`
+    pub direction: String,
+    /// Checksum of executed file
+    pub checksum: String,
+    /// RFC 3339 UTC timestamp
+    pub started_at: String,
+    /// RFC 3339 UTC timestamp
+    pub finished_at: String,
+    pub duration_ms: i64,
+    pub os_user: String,
+    pub hostname: String,
+    pub migren_version: String,
+}
+
+impl DatabaseHistoryEntry {
+    /// Baseline entries are treated as applied up files
+    pub fn direction(&self) -> Direction {
+        if self.direction == Direction::Down.as_str() {
+            Direction::Down
+        } else {
+            Direction::Up
+        }
+    }
+}
+
+/// Current UTC time in format saved to migren_history
+pub fn timestamp() -> String {
+    Utc::now().to_rfc3339_opts(SecondsFormat::Nanos, true)
+}
+
+pub fn os_user() -> String {
+    whoami::username()
+}
+
+pub fn hostname() -> String {
+    whoami::fallible::hostname().unwrap_or_else(|_| "unknown".to_string())
+}
+
+/// Saves entry after every other one. Entries are numbered in order of inserting,
+/// so their order does not depend on clocks of hosts. Caller is responsible for locking
+pub async fn insert_history_entry(
+    conn: &mut AnyConnection,
+    entry: &DatabaseHistoryEntry,
+) -> Result<()> {
+    let entry_id =
+        sqlx::query_scalar::<_, i64>("SELECT COALESCE(MAX(entry_id), 0) + 1 FROM migren_history")
+            .fetch_one(&mut *conn)
+            .await?;
+
+    sqlx::query(&Backend::of(conn).sql(
+        "INSERT INTO migren_history (entry_id, migration_id, name, direction, checksum, started_at, finished_at, duration_ms, os_user, hostname, migren_version) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11)",
+    ))
+    .bind(entry_id)
+    .bind(entry.migration_id)
+    .bind(&entry.name)
+    .bind(&entry.direction)
+    .bind(&entry.checksum)
+    .bind(&entry.started_at)
+    .bind(&entry.finished_at)
+    .bind(entry.duration_ms)
+    .bind(&entry.os_user)
+    .bind(&entry.hostname)
+    .bind(&entry.migren_version)
+    .execute(conn)
+    .await?;
+
+    Ok(())
+}
+
+/// Every history entry in order of saving. Empty if database is not initialized
+pub async fn history(conn: &mut AnyConnection) -> Result<Vec<DatabaseHistoryEntry>> {
+    if !table_exists(conn, "migren_history").await? {
+        return Ok(Vec::new());
+    }
+
+    Ok(sqlx::query_as::<_, DatabaseHistoryEntry>(
+        "SELECT migration_id, name, direction, checksum, started_at, finished_at, duration_ms, os_user, hostname, migren_version FROM migren_history ORDER BY entry_id",
+    )
+    .fetch_all(conn)
+    .await?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::test_connection;
+
+    fn entry(migration_id: i64, direction: Direction, started_at: &str) -> DatabaseHistoryEntry {
+        DatabaseHistoryEntry {
+            migration_id,
+            name: format!("migration_{migration_id}"),
+            direction: direction.as_str().to_string(),
+            checksum: String::new(),
+            started_at: started_at.to_string(),
+            finished_at: started_at.to_string(),
+            duration_ms: 0,
+            os_user: "user".to_string(),
+            hostname: "host".to_string(),
+            migren_version: env!("CARGO_PKG_VERSION").to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn keeps_order_of_saving() {
+        let mut conn = test_connection().await;
+        // Clock of the second host is behind, and the last two entries start at the same time
+        let entries = [
+            entry(1, Direction::Up, "2026-10-18T12:00:00Z"),
+            entry(2, Direction::Up, "2026-10-18T11:00:00Z"),
+            entry(2, Direction::Down, "2026-10-18T11:00:00Z"),
+        ];
+        for entry in &entries {
+            insert_history_entry(&mut conn, entry).await.unwrap();
+        }
+
+        let saved: Vec<(i64, String)> = history(&mut conn)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|entry| (entry.migration_id, entry.direction))
+            .collect();
+        assert_eq!(
+            saved,
+            [
+                (1, "up".to_string()),
+                (2, "up".to_string()),
+                (2, "down".to_string())
+            ]
+        );
+    }
+}
@@ -0,0 +1,364 @@
+use std::path::PathBuf;
+
+use regex::Regex;
+
+use crate::{
+    derictive_constants::is_transactional, schema::Backend, schema_diff::SchemaChanges,
+    splitter::split_sql,
+};
+
+/// Down file generated from up file of migration
+#[derive(Debug, Clone)]
+pub struct GeneratedDown {
+    pub file: PathBuf,
+    /// New content of down file
+    pub sql: String,
+    /// Statements of up file which could not be reverted. Down file has TODO comments for them
+    pub unsupported: Vec<String>,
+    /// Down file already has statements which would be replaced
+    pub overwrites: bool,
+}
+
+/// `CREATE` of object which is reverted by `DROP` with the same name
+const CREATE_REGEX: &str = r"(?is)^CREATE\s+(TABLE|VIEW|MATERIALIZED\s+VIEW|SEQUENCE|TYPE|SCHEMA)\s+(IF\s+NOT\s+EXISTS\s+)?([^\s(;]+)";
+
+/// `CREATE INDEX` with index name and table. Unnamed Postgres index does not match
+const CREATE_INDEX_REGEX: &str = r"(?is)^CREATE\s+(?:UNIQUE\s+)?INDEX\s+(CONCURRENTLY\s+)?(IF\s+NOT\s+EXISTS\s+)?([^\s(;]+)\s+(?:USING\s+[^\s]+\s+)?ON\s+(?:ONLY\s+)?([^\s(;]+)";
+
+/// `CREATE TRIGGER` with trigger name and table
+const CREATE_TRIGGER_REGEX: &str = r"(?is)^CREATE\s+(?:CONSTRAINT\s+)?TRIGGER\s+(IF\s+NOT\s+EXISTS\s+)?([^\s(;]+)\s.*?\bON\s+([^\s(;]+)";
+
+const ALTER_TABLE_REGEX: &str =
+    r"(?is)^ALTER\s+TABLE\s+(?:IF\s+EXISTS\s+)?(?:ONLY\s+)?([^\s]+)\s+(.*)$";
+
+const ADD_CONSTRAINT_REGEX: &str = r"(?is)^ADD\s+CONSTRAINT\s+([^\s]+)";
+
+const ADD_COLUMN_REGEX: &str = r"(?is)^ADD\s+(COLUMN\s+)?(?:IF\s+NOT\s+EXISTS\s+)?([^\s]+)";
+
+const RENAME_TABLE_REGEX: &str = r"(?is)^RENAME\s+TO\s+([^\s]+)$";
+
+const RENAME_COLUMN_REGEX: &str = r"(?is)^RENAME\s+(?:COLUMN\s+)?([^\s]+)\s+TO\s+([^\s]+)$";
+
+/// Words after `ADD` which start table constraint or index instead of column
+const NOT_COLUMN_WORDS: [&str; 7] = [
+    "PRIMARY",
+    "FOREIGN",
+    "UNIQUE",
+    "CHECK",
+    "CONSTRAINT",
+    "INDEX",
+    "KEY",
+];
+
+/// Statement code without leading comments
+fn strip_comments(sql: &str) -> &str {
+    let mut sql = sql.trim_start();
+    loop {
+        if sql.starts_with("--") {
+            sql = sql.find('\n').map_or("", |end| &sql[end..]).trim_start();
+        } else if sql.starts_with("/*") {
+            sql = sql
+                .find("*/")
+                .map_or("", |end| &sql[end + 2..])
+                .trim_start();
+        } else {
+            return sql;
+        }
+    }
+}
+
+/// True if `ALTER TABLE` actions have comma outside of parentheses, so there are several of them
+fn has_several_actions(actions: &str) -> bool {
+    let mut depth = 0i32;
+    for character in actions.chars() {
+        match character {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            ',' if depth == 0 => return true,
+            _ => {}
+        }
+    }
+    false
+}
+
+/// Statement reverting single `ALTER TABLE` action
+fn invert_alter_table(table: &str, action: &str) -> Option<String> {
+    if has_several_actions(action) {
+        return None;
+    }
+
+    if let Some(captures) = Regex::new(ADD_CONSTRAINT_REGEX).unwrap().captures(action) {
+        return Some(format!(
+            "ALTER TABLE {table} DROP CONSTRAINT {};",
+            &captures[1]
+        ));
+    }
+    if let Some(captures) = Regex::new(ADD_COLUMN_REGEX).unwrap().captures(action) {
+        let column = &captures[2];
+        let is_column = captures.get(1).is_some()
+            || !NOT_COLUMN_WORDS
+                .iter()
+                .any(|word| column.eq_ignore_ascii_case(word));
+        return is_column.then(|| format!("ALTER TABLE {table} DROP COLUMN {column};"));
+    }
+    if let Some(captures) = Regex::new(RENAME_TABLE_REGEX).unwrap().captures(action) {
+        return Some(format!("ALTER TABLE {} RENAME TO {table};", &captures[1]));
+    }
+    if let Some(captures) = Regex::new(RENAME_COLUMN_REGEX).unwrap().captures(action) {
+        return Some(format!(
+            "ALTER TABLE {table} RENAME COLUMN {} TO {};",
+            &captures[2], &captures[1]
+        ));
+    }
+    None
+}
+
+/// `DROP INDEX` for index created by `CREATE INDEX` captures
+fn drop_index(backend: Backend, captures: &regex::Captures) -> Option<String> {
+    let name = &captures[3];
+    let table = &captures[4];
+    // `CREATE INDEX ON table` of Postgres, name is generated by database
+    if name.eq_ignore_ascii_case("ON") {
+        return None;
+    }
+
+    let if_exists = match captures.get(2) {
+        Some(_) => "IF EXISTS ",
+        None => "",
+    };
+    Some(match backend {
+        Backend::MySql => format!("DROP INDEX {if_exists}{name} ON {table};"),
+        Backend::Postgres => {
+            let concurrently = match captures.get(1) {
+                Some(_) => "CONCURRENTLY ",
+                None => "",
+            };
+            // Index is created in schema of its table
+            let name = match table.rsplit_once('.') {
+                Some((schema, _)) if !name.contains('.') => format!("{schema}.{name}"),
+                _ => name.to_string(),
+            };
+            format!("DROP INDEX {concurrently}{if_exists}{name};")
+        }
+        Backend::Sqlite => format!("DROP INDEX {if_exists}{name};"),
+    })
+}
+
+/// Statement reverting `sql`. `None` if it can not be reverted mechanically
+fn invert_statement(sql: &str, backend: Backend) -> Option<String> {
+    let sql = strip_comments(sql);
+
+    if let Some(captures) = Regex::new(CREATE_REGEX).unwrap().captures(sql) {
+        let kind = captures[1]
+            .split_whitespace()
+            .collect::<Vec<_>>()
+            .join(" ")
+            .to_uppercase();
+        let if_exists = match captures.get(2) {
+            Some(_) => "IF EXISTS ",
+            None => "",
+        };
+        return Some(format!("DROP {kind} {if_exists}{};", &captures[3]));
+    }
+
+    if let Some(captures) = Regex::new(CREATE_INDEX_REGEX).unwrap().captures(sql) {
+        return drop_index(backend, &captures);
+    }
+
+    if let Some(captures) = Regex::new(CREATE_TRIGGER_REGEX).unwrap().captures(sql) {
+        let if_exists = match captures.get(1) {
+            Some(_) => "IF EXISTS ",
+            None => "",
+        };
+        let name = &captures[2];
+        return Some(match backend {
+            Backend::Postgres => format!("DROP TRIGGER {if_exists}{name} ON {};", &captures[3]),
+            _ => format!("DROP TRIGGER {if_exists}{name};"),
+        });
+    }
+
+    if let Some(captures) = Regex::new(ALTER_TABLE_REGEX).unwrap().captures(sql) {
+        return invert_alter_table(&captures[1], captures[2].trim());
+    }
+
+    None
+}
+
+/// Down file body reverting statements of up file in reverse order.
+/// Creating tables, indexes, views and other objects is reverted by dropping them,
+/// added columns and constraints are dropped and renames are reverted. Statements are written
+/// in dialect of `backend`, which decides how indexes and triggers are dropped. Every other
+/// statement, like dropped column or data update, gets TODO comment with statement itself
+pub fn invert_sql(up_sql: &str, backend: Backend) -> SchemaChanges {
+    let mut changes = SchemaChanges::default();
+    if !is_transactional(up_sql) {
+        changes.push("-- migren:no-transaction".to_string());
+    }
+
+    for statement in split_sql(up_sql, backend).into_iter().rev() {
+        if let Some(inverted) = invert_statement(&statement.sql, backend) {
+            changes.push(inverted);
+            continue;
+        }
+
+        let code = strip_comments(&statement.sql);
+        changes.unsupported(format!(
+            "can not revert statement at line {} of up file: {}",
+            statement.line,
+            code.lines().next().unwrap_or_default()
+        ));
+        for line in code.lines() {
+            changes.push(format!("-- {line}"));
+        }
+    }
+
+    changes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn inverts_statements() {
+        let cases = [
+            (
+                Backend::Sqlite,
+                "CREATE TABLE users (id INTEGER);",
+                "DROP TABLE users;",
+            ),
+            (
+                Backend::Sqlite,
+                "CREATE TABLE IF NOT EXISTS users(id INTEGER);",
+                "DROP TABLE IF EXISTS users;",
+            ),
+            (
+                Backend::Postgres,
+                "CREATE MATERIALIZED VIEW totals AS SELECT 1;",
+                "DROP MATERIALIZED VIEW totals;",
+            ),
+            (
+                Backend::Postgres,
+                "CREATE SEQUENCE ids;",
+                "DROP SEQUENCE ids;",
+            ),
+            (
+                Backend::Sqlite,
+                "CREATE UNIQUE INDEX users_name ON users (name);",
+                "DROP INDEX users_name;",
+            ),
+            (
+                Backend::Postgres,
+                "CREATE INDEX CONCURRENTLY IF NOT EXISTS users_name ON users (name);",
+                "DROP INDEX CONCURRENTLY IF EXISTS users_name;",
+            ),
+            (
+                Backend::Postgres,
+                "CREATE INDEX users_name ON ONLY app.users USING btree (name);",
+                "DROP INDEX app.users_name;",
+            ),
+            (
+                Backend::MySql,
+                "CREATE INDEX users_name USING BTREE ON users (name);",
+                "DROP INDEX users_name ON users;",
+            ),
+            (
+                Backend::Postgres,
+                "CREATE TRIGGER users_audit AFTER INSERT OR UPDATE OF name ON users FOR EACH ROW EXECUTE FUNCTION audit();",
+                "DROP TRIGGER users_audit ON users;",
+            ),
+            (
+                Backend::MySql,
+                "CREATE TRIGGER users_audit BEFORE INSERT ON users FOR EACH ROW SET NEW.name = TRIM(NEW.name);",
+                "DROP TRIGGER users_audit;",
+            ),
+            (
+                Backend::Sqlite,
+                "CREATE TRIGGER IF NOT EXISTS users_audit AFTER DELETE ON users BEGIN SELECT 1; END;",
+                "DROP TRIGGER IF EXISTS users_audit;",
+            ),
+            (
+                Backend::Postgres,
+                "ALTER TABLE users ADD COLUMN email TEXT;",
+                "ALTER TABLE users DROP COLUMN email;",
+            ),
+            (
+                Backend::MySql,
+                "ALTER TABLE users ADD email TEXT;",
+                "ALTER TABLE users DROP COLUMN email;",
+            ),
+            (
+                Backend::Postgres,
+                "ALTER TABLE users ADD CONSTRAINT users_email_key UNIQUE (email);",
+                "ALTER TABLE users DROP CONSTRAINT users_email_key;",
+            ),
+            (
+                Backend::Postgres,
+                "ALTER TABLE users RENAME TO accounts;",
+                "ALTER TABLE accounts RENAME TO users;",
+            ),
+            (
+                Backend::Sqlite,
+                "ALTER TABLE users RENAME COLUMN name TO full_name;",
+                "ALTER TABLE users RENAME COLUMN full_name TO name;",
+            ),
+        ];
+
+        for (backend, up_sql, expected) in cases {
+            let changes = invert_sql(up_sql, backend);
+            assert_eq!(changes.statements, [expected], "{backend:?}: {up_sql}");
+            assert!(changes.unsupported.is_empty(), "{backend:?}: {up_sql}");
+        }
+    }
+
+    #[test]
+    fn comments_statements_which_can_not_be_inverted() {
+        let cases = [
+            (Backend::Postgres, "CREATE INDEX ON users (name);"),
+            (Backend::Postgres, "ALTER TABLE users DROP COLUMN name;"),
+            (Backend::Postgres, "ALTER TABLE users ADD UNIQUE (email);"),
+            (
+                Backend::Postgres,
+                "ALTER TABLE users ADD COLUMN a TEXT, ADD COLUMN b TEXT;",
+            ),
+            (Backend::MySql, "UPDATE users SET name = 'x';"),
+        ];
+
+        for (backend, up_sql) in cases {
+            let changes = invert_sql(up_sql, backend);
+            assert_eq!(changes.unsupported.len(), 1, "{backend:?}: {up_sql}");
+            assert!(
+                changes.statements[0].starts_with("-- TODO: can not revert statement at line 1"),
+                "{backend:?}: {up_sql}"
+            );
+            assert_eq!(
+                changes.statements[1],
+                format!("-- {}", up_sql.trim_end_matches(';'))
+            );
+        }
+    }
+
+    #[test]
+    fn reverts_statements_in_reverse_order() {
+        let up_sql =
+            "-- users\nCREATE TABLE users (id INTEGER);\nCREATE INDEX users_id ON users (id);\n";
+        let changes = invert_sql(up_sql, Backend::Sqlite);
+        assert_eq!(
+            changes.statements,
+            ["DROP INDEX users_id;", "DROP TABLE users;"]
+        );
+    }
+
+    #[test]
+    fn keeps_no_transaction_directive() {
+        let up_sql = "-- migren:no-transaction\nCREATE INDEX CONCURRENTLY users_id ON users (id);";
+        let changes = invert_sql(up_sql, Backend::Postgres);
+        assert_eq!(
+            changes.statements,
+            [
+                "-- migren:no-transaction",
+                "DROP INDEX CONCURRENTLY users_id;"
+            ]
+        );
+    }
+}
@@ -0,0 +1,40 @@
+//! **Migren** is a small migration tool for relational databases.
+//!
+//! This crate can be used as a library to run migrations from your own service.
+//! Start with [`Migrator`]:
+//!
+//! ```no_run
+//! # async fn run() -> migren::Result<()> {
+//! let report = migren::Migrator::new("migrations")
+//!     .database_url("sqlite://app.db?mode=rwc")
+//!     .top()
+//!     .await?;
+//!
+//! println!("Database moved from {} to {}", report.from, report.to);
+//! # Ok(())
+//! # }
+//! ```
+pub mod database;
+pub mod derictive_constants;
+pub mod errors;
+pub mod features;
+pub mod history;
+pub mod invert;
+pub mod lock;
+pub mod migrator;
+pub mod rebuild;
+pub mod repeatable;
+pub mod roundtrip;
+pub mod schema;
+pub mod schema_diff;
+pub mod seed;
+pub mod splitter;
+pub mod util;
+pub mod verify;
+
+pub use errors::{MigrenError, Result};
+pub use features::{
+    DatabaseMigrationer, DatabaseMigrenData, IdFormat, MigrateOptions, MigrationData,
+    MigrationDrift, MigrationReport, MigrationsData, RedoReport, Squash, SquashedMigration,
+};
+pub use migrator::{MigrationStatus, MigrationStatusEntry, Migrator};
@@ -0,0 +1,211 @@
+use std::time::{Duration, Instant};
+
+use log::{debug, info, warn};
+use sqlx::AnyConnection;
+
+use crate::{
+    database::table_exists,
+    errors::{MigrenError, Result},
+    history::{hostname, os_user, timestamp},
+};
+
+/// Key for `pg_advisory_lock`. "migren" in ascii
+const PG_LOCK_KEY: i64 = 0x6D696772656E;
+/// Name for MySQL `GET_LOCK`
+const MYSQL_LOCK_NAME: &str = "migren";
+/// How often lock is retried while another migren holds it
+const LOCK_RETRY_INTERVAL: Duration = Duration::from_millis(500);
+
+const SQLITE_LOCK_TABLE_CREATE: &str = "
+CREATE TABLE IF NOT EXISTS migren_lock (
+    id INTEGER PRIMARY KEY,
+    holder TEXT,
+    acquired_at TEXT,
+    hostname TEXT,
+    pid BIGINT
+);
+";
+
+/// Default time to wait for lock held by another migren
+pub const DEFAULT_LOCK_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Description of this process saved as lock holder where backend allows it
+fn lock_holder() -> String {
+    format!("{}@{} (pid {})", os_user(), hostname(), std::process::id())
+}
+
+/// Returns false only if process surely does not exist. Processes can be checked on Linux only
+fn process_exists(pid: u32) -> bool {
+    if cfg!(target_os = "linux") {
+        std::path::Path::new("/proc").join(pid.to_string()).exists()
+    } else {
+        true
+    }
+}
+
+/// Removes SQLite lock row left by crashed migren on this host. Returns true if it was removed
+async fn remove_stale_sqlite_lock(conn: &mut AnyConnection) -> Result<bool> {
+    let row = sqlx::query_as::<_, (Option<String>, Option<i64>)>(
+        "SELECT hostname, pid FROM migren_lock WHERE id = 1",
+    )
+    .fetch_optional(&mut *conn)
+    .await?;
+    let Some((Some(holder_hostname), Some(pid))) = row else {
+        return Ok(false);
+    };
+    if holder_hostname != hostname() || process_exists(pid as u32) {
+        return Ok(false);
+    }
+
+    let res = sqlx::query("DELETE FROM migren_lock WHERE id = 1 AND hostname = $1 AND pid = $2")
+        .bind(&holder_hostname)
+        .bind(pid)
+        .execute(conn)
+        .await?;
+    if res.rows_affected() == 1 {
+        warn!("Removed migration lock of process {pid}, which does not run anymore");
+    }
+    Ok(res.rows_affected() == 1)
+}
+
+async fn try_lock_sqlite(conn: &mut AnyConnection) -> Result<bool> {
+    let res = sqlx::query(
+        "INSERT INTO migren_lock (id, holder, acquired_at, hostname, pid) VALUES (1, $1, $2, $3, $4) ON CONFLICT DO NOTHING",
+    )
+    .bind(lock_holder())
+    .bind(timestamp())
+    .bind(hostname())
+    .bind(std::process::id() as i64)
+    .execute(conn)
+    .await?;
+
+    Ok(res.rows_affected() == 1)
+}
+
+async fn try_lock(conn: &mut AnyConnection) -> Result<bool> {
+    match conn.backend_name() {
+        "PostgreSQL" => Ok(
+            sqlx::query_scalar::<_, bool>("SELECT pg_try_advisory_lock($1)")
+                .bind(PG_LOCK_KEY)
+                .fetch_one(conn)
+                .await?,
+        ),
+        "MySQL" => Ok(
+            sqlx::query_scalar::<_, Option<i64>>("SELECT GET_LOCK(?, 0)")
+                .bind(MYSQL_LOCK_NAME)
+                .fetch_one(conn)
+                .await?
+                == Some(1),
+        ),
+        _ => {
+            sqlx::query(SQLITE_LOCK_TABLE_CREATE)
+                .execute(&mut *conn)
+                .await?;
+            if try_lock_sqlite(conn).await? {
+                return Ok(true);
+            }
+
+            // Unlike advisory locks, lock row is not released when its holder dies
+            Ok(remove_stale_sqlite_lock(conn).await? && try_lock_sqlite(conn).await?)
+        }
+    }
+}
+
+/// Who holds the lock right now, if backend can tell it
+async fn lock_holder_info(conn: &mut AnyConnection) -> Result<Option<String>> {
+    match conn.backend_name() {
+        "PostgreSQL" => Ok(sqlx::query_scalar::<_, String>(
+            "SELECT CONCAT('pid ', a.pid, ', user ', a.usename, ', client ', COALESCE(a.client_addr::TEXT, 'local'), ', application ', a.application_name) FROM pg_locks l JOIN pg_stat_activity a ON a.pid = l.pid WHERE l.locktype = 'advisory' AND l.granted AND ((l.classid::BIGINT << 32) | l.objid::BIGINT) = $1 LIMIT 1",
+        )
+        .bind(PG_LOCK_KEY)
+        .fetch_optional(conn)
+        .await?),
+        "MySQL" => Ok(sqlx::query_scalar::<_, Option<i64>>(
+            "SELECT CAST(IS_USED_LOCK(?) AS SIGNED)",
+        )
+        .bind(MYSQL_LOCK_NAME)
+        .fetch_one(conn)
+        .await?
+        .map(|connection_id| format!("connection {connection_id}"))),
+        _ => Ok(sqlx::query_scalar::<_, String>(
+            "SELECT holder || ' since ' || acquired_at FROM migren_lock WHERE id = 1",
+        )
+        .fetch_optional(conn)
+        .await?),
+    }
+}
+
+/// Takes database-level lock, so only one migren can migrate database at once.
+/// Waits for lock held by someone else for `timeout`
+pub async fn lock(conn: &mut AnyConnection, timeout: Duration) -> Result<()> {
+    let started = Instant::now();
+    let mut logged_holder = false;
+
+    loop {
+        if try_lock(conn).await? {
+            debug!("Acquired migration lock");
+            return Ok(());
+        }
+
+        if !logged_holder {
+            let holder = lock_holder_info(conn).await?;
+            info!(
+                "Waiting for migration lock held by {}",
+                holder.as_deref().unwrap_or("unknown")
+            );
+            logged_holder = true;
+        }
+
+        if started.elapsed() >= timeout {
+            return Err(MigrenError::LockTimeout {
+                timeout,
+                holder: lock_holder_info(conn).await?,
+            });
+        }
+
+        tokio::time::sleep(LOCK_RETRY_INTERVAL).await;
+    }
+}
+
+/// Releases lock taken by [`lock`]
+pub async fn unlock(conn: &mut AnyConnection) -> Result<()> {
+    match conn.backend_name() {
+        "PostgreSQL" => {
+            sqlx::query("SELECT pg_advisory_unlock($1)")
+                .bind(PG_LOCK_KEY)
+                .execute(conn)
+                .await?;
+        }
+        "MySQL" => {
+            sqlx::query("SELECT RELEASE_LOCK(?)")
+                .bind(MYSQL_LOCK_NAME)
+                .execute(conn)
+                .await?;
+        }
+        _ => {
+            sqlx::query("DELETE FROM migren_lock WHERE id = 1")
+                .execute(conn)
+                .await?;
+        }
+    }
+
+    debug!("Released migration lock");
+    Ok(())
+}
+
+/// Removes migration lock left by migren which was killed while migrating SQLite database.
+/// Returns its holder. PostgreSQL and MySQL locks are released with connection of holder,
+/// so there is nothing to remove for them
+pub async fn force_unlock(conn: &mut AnyConnection) -> Result<Option<String>> {
+    if matches!(conn.backend_name(), "PostgreSQL" | "MySQL")
+        || !table_exists(conn, "migren_lock").await?
+    {
+        return Ok(None);
+    }
+
+    let holder = lock_holder_info(conn).await?;
+    sqlx::query("DELETE FROM migren_lock WHERE id = 1")
+        .execute(conn)
+        .await?;
+    Ok(holder)
+}
@@ -0,0 +1,46 @@
+use log::{Level, LevelFilter, Log, Metadata, Record};
+
+/// Prints log records as user-facing output.
+/// Info lines go to stdout, warnings and errors go to stderr.
+/// Other crates are shown only when they warn
+struct CliLogger {
+    /// Stdout is reserved for command result (like json), so info goes to stderr too
+    info_to_stderr: bool,
+}
+
+impl Log for CliLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &Record) {
+        let from_migren = record
+            .module_path()
+            .is_some_and(|module| module.starts_with("migren"));
+        if !self.enabled(record.metadata()) || (!from_migren && record.level() > Level::Warn) {
+            return;
+        }
+
+        match record.level() {
+            Level::Error => eprintln!("error: {}", record.args()),
+            Level::Warn => eprintln!("warning: {}", record.args()),
+            Level::Info if self.info_to_stderr => eprintln!("{}", record.args()),
+            Level::Info => println!("{}", record.args()),
+            level => eprintln!(
+                "[{} {}] {}",
+                level.as_str().to_lowercase(),
+                record.target(),
+                record.args()
+            ),
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+/// Installs logger which shows records up to `level`
+pub fn init(level: LevelFilter, info_to_stderr: bool) {
+    let logger = Box::leak(Box::new(CliLogger { info_to_stderr }));
+    log::set_logger(logger).expect("Logger is initialized only once");
+    log::set_max_level(level);
+}
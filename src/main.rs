@@ -1,49 +1,204 @@
 mod cli_args;
 mod commands;
-mod database;
 mod env_args;
-mod errors;
-mod features;
-mod derictive_constants;
-mod util;
+mod logger;
+
+use std::{error::Error, time::Duration};
 
 use clap::Parser;
 use dotenv::dotenv;
-use log::error;
-use util::create_dir_if_not_exists;
+use log::LevelFilter;
+use migren::{Migrator, MigrenError, errors};
 
-async fn run_migren() -> errors::Result<()> {
-    let cli = cli_args::CliArgs::parse();
+const EXIT_FAILURE: i32 = 1;
+/// Invalid environment, arguments or `.migren.json`
+const EXIT_CONFIG: i32 = 2;
+/// Database can not be moved to requested migration
+const EXIT_INVALID_PATH: i32 = 3;
+const EXIT_MISSING_FILES: i32 = 4;
+/// Connection, statement or lock failed
+const EXIT_DATABASE: i32 = 5;
+/// Applied migration files or schema file were changed
+const EXIT_DRIFT: i32 = 6;
+
+fn with_migrate_args(migrator: Migrator, args: &cli_args::MigrateArgs) -> Migrator {
+    migrator
+        .allow_drift(args.allow_drift)
+        .dry_run(args.dry_run)
+        .lock_timeout(Duration::from_secs(args.lock_timeout))
+}
+
+async fn run_migren(cli: &cli_args::CliArgs) -> errors::Result<()> {
+    let output = cli.output;
     let env_args = envy::from_env::<env_args::EnvArgs>()?;
 
-    create_dir_if_not_exists(&cli.directory)?;
-    std::env::set_current_dir(&cli.directory)?;
+    let mut migrator = Migrator::new(&cli.directory);
+    if let Some(database_url) = env_args.database_url {
+        migrator = migrator.database_url(database_url);
+    }
+    let production = env_args.migren_environment.is_some_and(|environment| {
+        matches!(
+            environment.trim().to_lowercase().as_str(),
+            "production" | "prod"
+        )
+    });
+    migrator = migrator.production(production);
 
     match &cli.command {
-        cli_args::Command::To { migration_id } => {
-                commands::to(&cli, &env_args, *migration_id).await
-            }
-        cli_args::Command::Top => commands::top(&cli, &env_args).await,
-        cli_args::Command::New { name } => commands::new(&cli, &env_args, name),
-        cli_args::Command::Status => commands::status(&cli, &env_args).await,
-        cli_args::Command::Exec { sql_file } => commands::exec(&cli, &env_args, sql_file).await,
+        cli_args::Command::To {
+            migration_id,
+            migrate,
+        } => {
+            commands::to(
+                &mut with_migrate_args(migrator, migrate),
+                *migration_id,
+                output,
+            )
+            .await
+        }
+        cli_args::Command::Top { migrate } => {
+            commands::top(&mut with_migrate_args(migrator, migrate), output).await
+        }
+        cli_args::Command::Up { steps, migrate } => {
+            commands::up(&mut with_migrate_args(migrator, migrate), *steps, output).await
+        }
+        cli_args::Command::Down { steps, migrate } => {
+            commands::down(&mut with_migrate_args(migrator, migrate), *steps, output).await
+        }
+        cli_args::Command::Baseline {
+            migration_id,
+            yes,
+            lock_timeout,
+        } => {
+            let mut migrator = migrator.lock_timeout(Duration::from_secs(*lock_timeout));
+            commands::baseline(&mut migrator, *migration_id, *yes, output).await
+        }
+        cli_args::Command::Redo {
+            steps,
+            check_schema,
+            migrate,
+        } => {
+            commands::redo(
+                &mut with_migrate_args(migrator, migrate),
+                *steps,
+                *check_schema,
+                output,
+            )
+            .await
+        }
+        cli_args::Command::New {
+            name,
+            from_schema: None,
+            ..
+        } => commands::new(&migrator, name, output),
+        cli_args::Command::New {
+            name,
+            from_schema: Some(from_schema),
+            shadow_url,
+        } => {
+            commands::new_from_schema(&migrator, name, from_schema, shadow_url.as_deref(), output)
+                .await
+        }
+        cli_args::Command::GenDown { migration_id, yes } => {
+            commands::gen_down(&migrator, *migration_id, *yes, output)
+        }
+        cli_args::Command::Merge { name } => commands::merge(&migrator, name, output),
+        cli_args::Command::Squash { from, to, name } => {
+            commands::squash(&migrator, *from, *to, name, output)
+        }
+        cli_args::Command::Rebuild { yes } => commands::rebuild(&migrator, *yes, output),
+        cli_args::Command::Test { scratch_url } => {
+            commands::test(&migrator, scratch_url.as_deref(), output).await
+        }
+        cli_args::Command::Verify => commands::verify(&migrator, output),
+        cli_args::Command::Schema { check } => {
+            commands::schema(&mut migrator, *check, output).await
+        }
+        cli_args::Command::SetIdFormat { id_format } => {
+            commands::set_id_format(&mut migrator, *id_format, output).await
+        }
+        cli_args::Command::Seed {
+            set,
+            dry_run,
+            lock_timeout,
+        } => {
+            let mut migrator = migrator
+                .dry_run(*dry_run)
+                .lock_timeout(Duration::from_secs(*lock_timeout));
+            commands::seed(&mut migrator, set, output).await
+        }
+        cli_args::Command::Status => commands::status(&mut migrator, output).await,
+        cli_args::Command::History => commands::history(&mut migrator, output).await,
+        cli_args::Command::AcceptChecksums { lock_timeout } => {
+            let mut migrator = migrator.lock_timeout(Duration::from_secs(*lock_timeout));
+            commands::accept_checksums(&mut migrator, output).await
+        }
+        cli_args::Command::Unlock => commands::unlock(&mut migrator, output).await,
+        cli_args::Command::Exec { sql_file } => {
+            commands::exec(&mut migrator, sql_file, output).await
+        }
     }?;
 
     Ok(())
 }
 
+/// Process exit code for error, so scripts can tell failures apart
+fn exit_code(err: &MigrenError) -> i32 {
+    match err {
+        MigrenError::Envy(_)
+        | MigrenError::Clap(_)
+        | MigrenError::SerdeJson(_)
+        | MigrenError::DatabaseNotConfigured
+        | MigrenError::ScratchDatabaseNotEmpty
+        | MigrenError::SeedSetNotFound(_)
+        | MigrenError::ProductionSeedRefused(_) => EXIT_CONFIG,
+        MigrenError::MigrationPathInvalid { .. }
+        | MigrenError::NothingToMerge(_)
+        | MigrenError::SquashedMigration { .. }
+        | MigrenError::AlreadyMigrated(_)
+        | MigrenError::VerificationFailed(_)
+        | MigrenError::MigrationNotFound(_) => EXIT_INVALID_PATH,
+        MigrenError::MigrationFilesDoesNotExsists(_) => EXIT_MISSING_FILES,
+        MigrenError::Database(_)
+        | MigrenError::StatementFailed { .. }
+        | MigrenError::LockTimeout { .. }
+        | MigrenError::RedoStateMismatch(_) => EXIT_DATABASE,
+        MigrenError::MigrationDrift(_) | MigrenError::SchemaStale(_) => EXIT_DRIFT,
+        MigrenError::Io(_) | MigrenError::TestFailed(_) => EXIT_FAILURE,
+    }
+}
+
+fn print_error(err: &MigrenError) {
+    eprintln!("error: {err}");
+
+    let mut source = err.source();
+    while let Some(cause) = source {
+        eprintln!("  caused by: {cause}");
+        source = cause.source();
+    }
+}
+
 #[tokio::main]
 async fn main() {
     dotenv().ok();
-    env_logger::init();
 
-    let res: errors::Result<()> = run_migren().await;
+    let cli = cli_args::CliArgs::parse();
+    let json_output = cli.output == cli_args::OutputFormat::Json;
+    let level = match (cli.quiet, cli.verbose) {
+        (true, _) => LevelFilter::Warn,
+        (false, 0) => LevelFilter::Info,
+        (false, 1) => LevelFilter::Debug,
+        (false, _) => LevelFilter::Trace,
+    };
+    logger::init(level, json_output);
 
-    match res {
-        Ok(_) => {}
-        Err(err) => {
-            error!("Program failed: {:?}", err);
-            panic!();
+    if let Err(err) = run_migren(&cli).await {
+        if json_output {
+            let error = serde_json::json!({ "error": err.to_json() });
+            println!("{}", serde_json::to_string_pretty(&error).unwrap());
+        } else {
+            print_error(&err);
         }
+        std::process::exit(exit_code(&err));
     }
 }
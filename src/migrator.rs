@@ -0,0 +1,562 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use serde::Serialize;
+use sqlx::AnyConnection;
+
+use crate::{
+    database::{ScratchDatabase, connect, has_integer_migration_ids, is_initialized},
+    errors::{MigrenError, Result},
+    features::{
+        DatabaseMigrationChecksum, DatabaseMigrationInProgress, DatabaseMigrationer,
+        DatabaseMigrenData, IdFormat, MigrateOptions, MigrationData, MigrationDrift,
+        MigrationReport, MigrationsData, RedoReport, execute_statements,
+    },
+    history::DatabaseHistoryEntry,
+    invert::{GeneratedDown, invert_sql},
+    lock::force_unlock,
+    rebuild::{RebuiltMigrations, rebuild_migrations_data},
+    roundtrip::{MigrationTest, test_migrations},
+    schema::{Backend, DatabaseSchema, read_schema, schema_file_diff, write_schema_file},
+    schema_diff::{GeneratedMigration, clear_database, diff_schemas},
+    seed::{SeedReport, seed},
+    splitter::split_sql,
+    util::{
+        DiffLine, MIGRATIONS_FILE_NAME, assert_migration_files_exists, create_dir_if_not_exists,
+        load_migrations_data, save_migrations_data,
+    },
+    verify::{VerifyIssue, verify_migrations_data},
+};
+
+/// Schema of applied migrations and schema created by target sql, read from empty scratch database
+async fn read_schemas(
+    conn: &mut AnyConnection,
+    migrations_data: &MigrationsData,
+    target_schema: &Path,
+    target_sql: &str,
+) -> Result<(DatabaseSchema, DatabaseSchema)> {
+    if !read_schema(conn).await?.is_empty() {
+        return Err(MigrenError::ScratchDatabaseNotEmpty);
+    }
+
+    conn.to(
+        migrations_data,
+        migrations_data.migrations_counter,
+        &MigrateOptions::default(),
+    )
+    .await?;
+    let current = read_schema(conn).await?;
+    clear_database(conn, &current).await?;
+
+    execute_statements(conn, target_schema, target_sql).await?;
+    let target = read_schema(conn).await?;
+    clear_database(conn, &target).await?;
+
+    Ok((current, target))
+}
+
+/// Where migrator takes database connection from
+enum DatabaseSource {
+    Url(String),
+    Connection(AnyConnection),
+}
+
+/// Migration row of status listing
+#[derive(Serialize, Debug, Clone)]
+pub struct MigrationStatusEntry {
+    pub id: u64,
+    pub name: String,
+    /// Migration is applied to database
+    pub applied: bool,
+    /// Database is at this migration
+    pub current: bool,
+    /// Files of migration which do not exist on disk
+    pub missing_files: Vec<PathBuf>,
+}
+
+/// Status of migrations directory and database
+#[derive(Serialize, Debug)]
+pub struct MigrationStatus {
+    /// Last migration id from `.migren.json`
+    pub migrations_counter: u64,
+    /// Migren version `.migren.json` was created with
+    pub migren_version: String,
+    /// Migren tables exist in database. Status never creates them
+    pub initialized: bool,
+    /// Data stored in migren_data table
+    pub database: DatabaseMigrenData,
+    /// Migration database is at
+    pub last_migration_applied: u64,
+    /// Migration database is at. `None` if it is not present in `.migren.json`
+    pub current_migration: Option<MigrationData>,
+    /// Database is at migration which is not present in `.migren.json` and was not squashed.
+    /// Usually means database was migrated from newer checkout
+    pub database_ahead: bool,
+    /// Every migration in order of applying
+    pub migrations: Vec<MigrationStatusEntry>,
+    /// Applied migrations whose files were changed on disk
+    pub drift: Vec<MigrationDrift>,
+    /// Non-transactional migration which failed halfway
+    pub in_progress: Option<DatabaseMigrationInProgress>,
+    /// Migrations without next migrations. More than one means migrations diverged
+    pub heads: Vec<u64>,
+    /// Migration database position was squashed into. Such database has to be moved manually
+    pub squashed_into: Option<u64>,
+}
+
+/// Runs migrations from directory against database.
+///
+/// Connection is opened lazily on first command which needs it.
+pub struct Migrator {
+    directory: PathBuf,
+    database: Option<DatabaseSource>,
+    target: Option<u64>,
+    options: MigrateOptions,
+}
+
+impl Migrator {
+    /// Creates migrator for migrations directory
+    pub fn new(directory: impl Into<PathBuf>) -> Self {
+        Self {
+            directory: directory.into(),
+            database: None,
+            target: None,
+            options: MigrateOptions::default(),
+        }
+    }
+
+    /// Database to connect to. Driver is recognised by schema
+    pub fn database_url(mut self, url: impl Into<String>) -> Self {
+        self.database = Some(DatabaseSource::Url(url.into()));
+        self
+    }
+
+    /// Use already opened connection instead of connecting by url
+    pub fn connection(mut self, connection: AnyConnection) -> Self {
+        self.database = Some(DatabaseSource::Connection(connection));
+        self
+    }
+
+    /// Migration [`Migrator::run`] moves database to. Last migration if not set
+    pub fn target(mut self, migration_id: u64) -> Self {
+        self.target = Some(migration_id);
+        self
+    }
+
+    /// Apply migrations even if already applied files were changed on disk
+    pub fn allow_drift(mut self, allow_drift: bool) -> Self {
+        self.options.allow_drift = allow_drift;
+        self
+    }
+
+    /// Only show what would be applied, without executing anything
+    pub fn dry_run(mut self, dry_run: bool) -> Self {
+        self.options.dry_run = dry_run;
+        self
+    }
+
+    /// How long to wait for migration lock held by another migren
+    pub fn lock_timeout(mut self, timeout: Duration) -> Self {
+        self.options.lock_timeout = timeout;
+        self
+    }
+
+    /// Marks database as production one. Only base seed set and sets allowed by `.migren.json`
+    /// are applied to it
+    pub fn production(mut self, production: bool) -> Self {
+        self.options.production = production;
+        self
+    }
+
+    pub fn directory(&self) -> &Path {
+        &self.directory
+    }
+
+    /// Returns opened connection, if migrator has one
+    pub fn into_connection(self) -> Option<AnyConnection> {
+        match self.database {
+            Some(DatabaseSource::Connection(connection)) => Some(connection),
+            _ => None,
+        }
+    }
+
+    /// Loads `.migren.json` from migrations directory. Creates directory and file if needed
+    pub fn migrations_data(&self) -> Result<MigrationsData> {
+        create_dir_if_not_exists(&self.directory)?;
+        load_migrations_data(&self.directory)
+    }
+
+    /// Backend of configured database, without connecting to it. SQLite if database is not set
+    fn backend(&self) -> Backend {
+        match &self.database {
+            Some(DatabaseSource::Url(url)) => Backend::from_url(url),
+            Some(DatabaseSource::Connection(connection)) => Backend::of(connection),
+            None => Backend::Sqlite,
+        }
+    }
+
+    async fn db(&mut self) -> Result<&mut AnyConnection> {
+        if let Some(DatabaseSource::Url(url)) = &self.database {
+            let connection = connect(url).await?;
+            self.database = Some(DatabaseSource::Connection(connection));
+        }
+
+        match &mut self.database {
+            Some(DatabaseSource::Connection(connection)) => Ok(connection),
+            _ => Err(MigrenError::DatabaseNotConfigured),
+        }
+    }
+
+    /// Moves database to target migration, or to the last one if target is not set
+    pub async fn run(&mut self) -> Result<MigrationReport> {
+        match self.target {
+            Some(migration_id) => self.to(migration_id).await,
+            None => self.top().await,
+        }
+    }
+
+    /// Moves database to selected migration (can be used as rollback as well)
+    pub async fn to(&mut self, migration_id: u64) -> Result<MigrationReport> {
+        let migrations_data = self.migrations_data()?;
+        let options = self.options.clone();
+        self.db()
+            .await?
+            .to(&migrations_data, migration_id, &options)
+            .await
+    }
+
+    /// Moves database to last added migration
+    pub async fn top(&mut self) -> Result<MigrationReport> {
+        let migrations_data = self.migrations_data()?;
+        let to_migration = migrations_data.migrations_counter;
+        let options = self.options.clone();
+        self.db()
+            .await?
+            .to(&migrations_data, to_migration, &options)
+            .await
+    }
+
+    /// Applies `steps` next migrations. Moves to last migration if `steps` is not set
+    pub async fn up(&mut self, steps: Option<u64>) -> Result<MigrationReport> {
+        let migrations_data = self.migrations_data()?;
+        let options = self.options.clone();
+        self.db().await?.up(&migrations_data, steps, &options).await
+    }
+
+    /// Reverts `steps` last applied migrations
+    pub async fn down(&mut self, steps: u64) -> Result<MigrationReport> {
+        let migrations_data = self.migrations_data()?;
+        let options = self.options.clone();
+        self.db()
+            .await?
+            .down(&migrations_data, steps, &options)
+            .await
+    }
+
+    /// Records database which was not migrated yet as being at migration,
+    /// without executing any files
+    pub async fn baseline(&mut self, migration_id: u64) -> Result<()> {
+        let migrations_data = self.migrations_data()?;
+        let options = self.options.clone();
+        self.db()
+            .await?
+            .baseline(&migrations_data, migration_id, &options)
+            .await
+    }
+
+    /// Reverts last `steps` migrations and applies them back. With `check_schema`
+    /// fails if down files do not restore schema up files started from
+    pub async fn redo(&mut self, steps: u64, check_schema: bool) -> Result<RedoReport> {
+        let migrations_data = self.migrations_data()?;
+        let options = self.options.clone();
+        self.db()
+            .await?
+            .redo(&migrations_data, steps, check_schema, &options)
+            .await
+    }
+
+    pub async fn status(&mut self) -> Result<MigrationStatus> {
+        let migrations_data = self.migrations_data()?;
+        let db = self.db().await?;
+        let initialized = is_initialized(db).await?;
+        let database = db.migren_data().await?;
+        let last_migration_applied = db.last_migration_applied(&migrations_data).await?;
+        let drift = db.drift(&migrations_data).await?;
+        let in_progress = db.in_progress().await?;
+        let heads = migrations_data.heads();
+        let squashed_into = migrations_data.squashed_into(last_migration_applied);
+        let current_migration = migrations_data
+            .migration_by_id(last_migration_applied)
+            .cloned();
+        let database_ahead = current_migration.is_none() && squashed_into.is_none();
+
+        let applied = migrations_data
+            .ancestors(last_migration_applied)
+            .unwrap_or_default();
+        let ids = migrations_data.migrations.iter().map(|m| m.id).collect();
+        // Circular migrations can not be ordered, `verify` reports them
+        let order = migrations_data.topological_order(&ids).unwrap_or_else(|_| {
+            let mut ids: Vec<u64> = ids.into_iter().collect();
+            ids.sort();
+            ids
+        });
+
+        let mut migrations = Vec::with_capacity(order.len());
+        for migration in order
+            .into_iter()
+            .filter_map(|id| migrations_data.migration_by_id(id))
+        {
+            let mut missing_files = Vec::new();
+            // 0 migration has no files
+            if migration.id != 0 {
+                for file in [
+                    migrations_data.up_file(migration),
+                    migrations_data.down_file(migration),
+                ] {
+                    if !std::fs::exists(&file)? {
+                        missing_files.push(file);
+                    }
+                }
+            }
+
+            migrations.push(MigrationStatusEntry {
+                id: migration.id,
+                name: migration.name.clone(),
+                applied: applied.contains(&migration.id),
+                current: migration.id == last_migration_applied,
+                missing_files,
+            });
+        }
+
+        Ok(MigrationStatus {
+            migrations_counter: migrations_data.migrations_counter,
+            migren_version: migrations_data.migren_version,
+            initialized,
+            database,
+            last_migration_applied,
+            current_migration,
+            database_ahead,
+            migrations,
+            drift,
+            in_progress,
+            heads,
+            squashed_into,
+        })
+    }
+
+    /// Writes database schema into `schema.sql` of migrations directory
+    pub async fn write_schema(&mut self) -> Result<PathBuf> {
+        create_dir_if_not_exists(&self.directory)?;
+        let directory = self.directory.clone();
+        write_schema_file(self.db().await?, &directory).await
+    }
+
+    /// Changed lines from `schema.sql` of migrations directory to database schema.
+    /// Empty if schema file is up to date
+    pub async fn schema_diff(&mut self) -> Result<Vec<DiffLine>> {
+        let directory = self.directory.clone();
+        schema_file_diff(self.db().await?, &directory).await
+    }
+
+    /// Applies new and changed files of seed set from `seeds/<set>` directory
+    pub async fn seed(&mut self, set: &str) -> Result<SeedReport> {
+        let migrations_data = self.migrations_data()?;
+        let options = self.options.clone();
+        seed(self.db().await?, &migrations_data, set, &options).await
+    }
+
+    /// Every applied and reverted migration, oldest first
+    pub async fn history(&mut self) -> Result<Vec<DatabaseHistoryEntry>> {
+        self.db().await?.history().await
+    }
+
+    /// Saves checksums of files on disk as applied ones. Use it after intentional edits
+    pub async fn accept_checksums(&mut self) -> Result<Vec<DatabaseMigrationChecksum>> {
+        let migrations_data = self.migrations_data()?;
+        let options = self.options.clone();
+        self.db()
+            .await?
+            .accept_checksums(&migrations_data, &options)
+            .await
+    }
+
+    /// Removes migration lock left by migren killed while migrating SQLite database.
+    /// Returns holder of removed lock
+    pub async fn force_unlock(&mut self) -> Result<Option<String>> {
+        force_unlock(self.db().await?).await
+    }
+
+    /// Creates new migration files and saves it into `.migren.json`
+    pub fn new_migration(&self, name: &str) -> Result<MigrationData> {
+        let mut migrations_data = self.migrations_data()?;
+        let migration = migrations_data.new_migration(name)?.clone();
+        save_migrations_data(&migrations_data)?;
+
+        Ok(migration)
+    }
+
+    /// Changes how ids of new migrations are generated. Existing migrations keep their ids
+    pub fn set_id_format(&self, id_format: IdFormat) -> Result<()> {
+        let mut migrations_data = self.migrations_data()?;
+        migrations_data.id_format = id_format;
+        save_migrations_data(&migrations_data)?;
+
+        Ok(())
+    }
+
+    /// Returns true if database keeps migration ids in `INTEGER` column, which is too small
+    /// for timestamp ids. Such column has to be changed to `BIGINT` before using them
+    pub async fn has_integer_migration_ids(&mut self) -> Result<bool> {
+        has_integer_migration_ids(self.db().await?).await
+    }
+
+    /// Builds `.migren.json` from migration files on disk. Does not save it
+    pub fn rebuild(&self) -> Result<RebuiltMigrations> {
+        create_dir_if_not_exists(&self.directory)?;
+        rebuild_migrations_data(&self.directory)
+    }
+
+    /// Round-trip tests every migration on scratch database: applies up file, reverts it
+    /// and applies it again, checking schema after every step.
+    /// Uses temporary SQLite database if `scratch_url` is not set. Never uses migrator database.
+    /// Database at `scratch_url` must be empty and is left empty
+    pub async fn test(&self, scratch_url: Option<&str>) -> Result<Vec<MigrationTest>> {
+        let migrations_data = self.migrations_data()?;
+
+        let mut scratch = ScratchDatabase::open(scratch_url).await?;
+        let tests = test_migrations(&mut scratch.conn, &migrations_data).await;
+        // Database which was not empty is left as it is
+        let cleared = match tests {
+            Err(MigrenError::ScratchDatabaseNotEmpty) => Ok(()),
+            _ => {
+                async {
+                    let schema = read_schema(&mut scratch.conn).await?;
+                    clear_database(&mut scratch.conn, &schema).await
+                }
+                .await
+            }
+        };
+        scratch.close().await?;
+
+        let tests = tests?;
+        cleared?;
+        Ok(tests)
+    }
+
+    /// Creates migration moving schema of current migrations to schema created by `target_schema`
+    /// sql file. Both schemas are read from scratch database: temporary SQLite database
+    /// or empty database at `shadow_url`, which is left empty.
+    /// Returns `None` and creates nothing if schemas are the same
+    pub async fn generate_migration(
+        &self,
+        name: &str,
+        target_schema: &Path,
+        shadow_url: Option<&str>,
+    ) -> Result<Option<GeneratedMigration>> {
+        let migrations_data = self.migrations_data()?;
+        let target_sql = fs::read_to_string(target_schema)?;
+
+        let mut scratch = ScratchDatabase::open(shadow_url).await?;
+        let schemas = read_schemas(
+            &mut scratch.conn,
+            &migrations_data,
+            target_schema,
+            &target_sql,
+        )
+        .await;
+        scratch.close().await?;
+        let (current, target) = schemas?;
+
+        let up = diff_schemas(&current, &target);
+        if up.is_empty() {
+            return Ok(None);
+        }
+        let down = diff_schemas(&target, &current);
+
+        let migration = self.new_migration(name)?;
+        for (file, direction, changes) in [
+            (&migration.files.up_migration_file, "up", &up),
+            (&migration.files.down_migration_file, "down", &down),
+        ] {
+            fs::write(
+                self.directory.join(file),
+                format!(
+                    "-- {} - {name} {direction} query\n{}\n",
+                    migration.id,
+                    changes.to_sql()
+                ),
+            )?;
+        }
+
+        Ok(Some(GeneratedMigration {
+            migration,
+            unsupported: up.unsupported,
+        }))
+    }
+
+    /// Builds down file of migration from its up file, reverting statements in reverse order.
+    /// Does not write it
+    pub fn generate_down(&self, migration_id: u64) -> Result<GeneratedDown> {
+        let migrations_data = self.migrations_data()?;
+        let migration = migrations_data
+            .migration_by_id(migration_id)
+            .filter(|migration| migration.id != 0)
+            .ok_or(MigrenError::MigrationNotFound(migration_id))?;
+        assert_migration_files_exists(&self.directory, migration)?;
+
+        let up_sql = fs::read_to_string(migrations_data.up_file(migration))?;
+        let file = migrations_data.down_file(migration);
+        let backend = self.backend();
+        let overwrites = !split_sql(&fs::read_to_string(&file)?, backend).is_empty();
+        let changes = invert_sql(&up_sql, backend);
+
+        Ok(GeneratedDown {
+            sql: format!(
+                "-- {} - {} down query\n{}\n",
+                migration.id,
+                migration.name,
+                changes.to_sql()
+            ),
+            file,
+            unsupported: changes.unsupported,
+            overwrites,
+        })
+    }
+
+    /// Checks `.migren.json` links and migration files. Does not need database
+    /// and does not create `.migren.json`, missing one is reported as issue
+    pub fn verify(&self) -> Result<Vec<VerifyIssue>> {
+        let migrations_file = self.directory.join(MIGRATIONS_FILE_NAME);
+        if !fs::exists(&migrations_file)? {
+            return Ok(vec![VerifyIssue::MissingMigrationsFile(migrations_file)]);
+        }
+
+        verify_migrations_data(&load_migrations_data(&self.directory)?)
+    }
+
+    /// Squashes migrations from `from` to `to` (inclusive) into single migration with `to` id
+    pub fn squash(&self, from: u64, to: u64, name: &str) -> Result<MigrationData> {
+        let mut migrations_data = self.migrations_data()?;
+        Ok(migrations_data.squash(from, to, name)?.clone())
+    }
+
+    /// Creates merge migration after every head of diverged migrations
+    pub fn merge(&self, name: &str) -> Result<MigrationData> {
+        let mut migrations_data = self.migrations_data()?;
+        let migration = migrations_data.merge_heads(name)?.clone();
+        save_migrations_data(&migrations_data)?;
+
+        Ok(migration)
+    }
+
+    /// Executes sql query against database
+    pub async fn exec(
+        &mut self,
+        sql_query: &str,
+    ) -> Result<<sqlx::Any as sqlx::Database>::QueryResult> {
+        self.db().await?.exec(sql_query).await
+    }
+}
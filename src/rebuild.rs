@@ -0,0 +1,281 @@
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use regex::Regex;
+
+use crate::{
+    errors::Result,
+    features::{IdFormat, MigrationData, MigrationFiles, MigrationsData},
+    schema::SCHEMA_FILE_NAME,
+    util::MIGRATIONS_FILE_NAME,
+};
+
+/// Smallest timestamp id. Ids bigger than it were generated with [`IdFormat::Timestamp`]
+const MIN_TIMESTAMP_ID: u64 = 19700101000000;
+
+/// Migration files found in migrations directory
+#[derive(Debug, Default)]
+pub struct ScannedMigrations {
+    /// Migrations which have both up and down files, ordered by id
+    pub migrations: Vec<MigrationData>,
+    /// Up files without down file and down files without up file
+    pub unpaired: Vec<PathBuf>,
+    /// `.sql` files which do not look like migration files
+    pub orphaned: Vec<PathBuf>,
+}
+
+/// `.migren.json` rebuilt from migration files
+#[derive(Debug)]
+pub struct RebuiltMigrations {
+    /// Current content of `.migren.json`. Pretty printed if it can be parsed
+    pub current: Option<String>,
+    pub rebuilt: MigrationsData,
+    pub unpaired: Vec<PathBuf>,
+    pub orphaned: Vec<PathBuf>,
+}
+
+/// Finds `{id}_{name}_up.sql` and `{id}_{name}_down.sql` pairs in migrations directory
+pub fn scan_migrations(directory: &Path) -> Result<ScannedMigrations> {
+    let file_regex = Regex::new(r"^(\d+)_(.+)_(up|down)\.sql$").unwrap();
+    let mut found: BTreeMap<(u64, String), (Option<PathBuf>, Option<PathBuf>)> = BTreeMap::new();
+    let mut scanned = ScannedMigrations::default();
+
+    let mut file_names: Vec<String> = fs::read_dir(directory)?
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_type().is_ok_and(|file_type| file_type.is_file()))
+        .map(|entry| entry.file_name().to_string_lossy().to_string())
+        .filter(|file_name| file_name.ends_with(".sql") && file_name != SCHEMA_FILE_NAME)
+        .collect();
+    file_names.sort();
+
+    for file_name in file_names {
+        let Some(captures) = file_regex.captures(&file_name) else {
+            scanned.orphaned.push(PathBuf::from(file_name));
+            continue;
+        };
+        let Some(id) = captures[1].parse::<u64>().ok().filter(|id| *id != 0) else {
+            scanned.orphaned.push(PathBuf::from(file_name));
+            continue;
+        };
+
+        let files = found.entry((id, captures[2].to_string())).or_default();
+        if &captures[3] == "up" {
+            files.0 = Some(PathBuf::from(&file_name));
+        } else {
+            files.1 = Some(PathBuf::from(&file_name));
+        }
+    }
+
+    for ((id, name), files) in found {
+        match files {
+            // Only the first of several migrations with the same id is linked
+            (Some(up_migration_file), Some(down_migration_file))
+                if scanned.migrations.last().is_some_and(|last| last.id == id) =>
+            {
+                scanned.orphaned.push(up_migration_file);
+                scanned.orphaned.push(down_migration_file);
+            }
+            (Some(up_migration_file), Some(down_migration_file)) => {
+                scanned.migrations.push(MigrationData {
+                    files: MigrationFiles {
+                        up_migration_file,
+                        down_migration_file,
+                    },
+                    name,
+                    id,
+                    prev_migration_ids: Vec::new(),
+                    next_migration_ids: Vec::new(),
+                });
+            }
+            (Some(file), None) | (None, Some(file)) => scanned.unpaired.push(file),
+            (None, None) => {}
+        }
+    }
+
+    Ok(scanned)
+}
+
+/// Builds `.migren.json` from migration files, linking migrations in id order
+pub fn rebuild_migrations_data(directory: &Path) -> Result<RebuiltMigrations> {
+    let migrations_file = directory.join(MIGRATIONS_FILE_NAME);
+    let current_raw = if fs::exists(&migrations_file)? {
+        Some(fs::read_to_string(&migrations_file)?)
+    } else {
+        None
+    };
+    let current_data = current_raw
+        .as_deref()
+        .and_then(|raw| serde_json::from_str::<MigrationsData>(raw).ok());
+
+    let scanned = scan_migrations(directory)?;
+    let mut rebuilt = MigrationsData {
+        directory: directory.to_path_buf(),
+        ..Default::default()
+    };
+
+    if let Some(current_data) = &current_data {
+        rebuilt.squashes = current_data.squashes.clone();
+        rebuilt.production_seed_sets = current_data.production_seed_sets.clone();
+    }
+    rebuilt.id_format = match &current_data {
+        Some(current_data) => current_data.id_format,
+        None if scanned
+            .migrations
+            .last()
+            .is_some_and(|migration| migration.id >= MIN_TIMESTAMP_ID) =>
+        {
+            IdFormat::Timestamp
+        }
+        None => IdFormat::Counter,
+    };
+
+    for mut migration in scanned.migrations {
+        let prev_migration = rebuilt.migrations.last_mut().unwrap();
+        prev_migration.next_migration_ids = vec![migration.id];
+        migration.prev_migration_ids = vec![prev_migration.id];
+
+        rebuilt.migrations_counter = migration.id;
+        rebuilt.migrations.push(migration);
+    }
+
+    let current = match (&current_data, current_raw) {
+        (Some(current_data), _) => Some(serde_json::to_string_pretty(current_data)?),
+        (None, raw) => raw,
+    };
+
+    Ok(RebuiltMigrations {
+        current,
+        rebuilt,
+        unpaired: scanned.unpaired,
+        orphaned: scanned.orphaned,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::test_directory;
+
+    fn write_files(directory: &Path, file_names: &[&str]) {
+        for file_name in file_names {
+            fs::write(directory.join(file_name), "").unwrap();
+        }
+    }
+
+    #[test]
+    fn pairs_migration_files() {
+        let directory = test_directory("pairs_migration_files");
+        write_files(
+            &directory,
+            &[
+                "2_add_users_down.sql",
+                "10_two_words_up.sql",
+                "2_add_users_up.sql",
+                "10_two_words_down.sql",
+            ],
+        );
+
+        let scanned = scan_migrations(&directory).unwrap();
+        let migrations: Vec<(u64, &str)> = scanned
+            .migrations
+            .iter()
+            .map(|migration| (migration.id, migration.name.as_str()))
+            .collect();
+        assert_eq!(migrations, [(2, "add_users"), (10, "two_words")]);
+        assert_eq!(
+            scanned.migrations[0].files.up_migration_file,
+            PathBuf::from("2_add_users_up.sql")
+        );
+        assert!(scanned.unpaired.is_empty());
+        assert!(scanned.orphaned.is_empty());
+    }
+
+    #[test]
+    fn reports_unpaired_and_orphaned_files() {
+        let directory = test_directory("reports_unpaired_and_orphaned_files");
+        write_files(
+            &directory,
+            &[
+                "1_a_up.sql",
+                "2_b_down.sql",
+                "0_initial_up.sql",
+                "0_initial_down.sql",
+                "notes.sql",
+                "schema.sql",
+                "readme.txt",
+            ],
+        );
+        fs::create_dir(directory.join("3_dir_up.sql")).unwrap();
+
+        let scanned = scan_migrations(&directory).unwrap();
+        assert!(scanned.migrations.is_empty());
+        assert_eq!(
+            scanned.unpaired,
+            [PathBuf::from("1_a_up.sql"), PathBuf::from("2_b_down.sql")]
+        );
+        assert_eq!(
+            scanned.orphaned,
+            [
+                PathBuf::from("0_initial_down.sql"),
+                PathBuf::from("0_initial_up.sql"),
+                PathBuf::from("notes.sql")
+            ]
+        );
+    }
+
+    #[test]
+    fn links_only_first_migration_with_same_id() {
+        let directory = test_directory("links_only_first_migration_with_same_id");
+        write_files(
+            &directory,
+            &["1_a_up.sql", "1_a_down.sql", "1_b_up.sql", "1_b_down.sql"],
+        );
+
+        let scanned = scan_migrations(&directory).unwrap();
+        assert_eq!(scanned.migrations.len(), 1);
+        assert_eq!(scanned.migrations[0].name, "a");
+        assert_eq!(
+            scanned.orphaned,
+            [PathBuf::from("1_b_up.sql"), PathBuf::from("1_b_down.sql")]
+        );
+    }
+
+    #[test]
+    fn rebuilds_linked_migrations() {
+        let directory = test_directory("rebuilds_linked_migrations");
+        write_files(
+            &directory,
+            &["1_a_up.sql", "1_a_down.sql", "3_b_up.sql", "3_b_down.sql"],
+        );
+
+        let rebuilt = rebuild_migrations_data(&directory).unwrap();
+        assert!(rebuilt.current.is_none());
+        let data = rebuilt.rebuilt;
+        assert_eq!(data.migrations_counter, 3);
+        assert_eq!(data.id_format, IdFormat::Counter);
+        assert_eq!(data.migration_by_id(0).unwrap().next_migration_ids, [1]);
+        assert_eq!(data.migration_by_id(1).unwrap().next_migration_ids, [3]);
+        assert_eq!(data.migration_by_id(3).unwrap().prev_migration_ids, [1]);
+        assert!(
+            data.migration_by_id(3)
+                .unwrap()
+                .next_migration_ids
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn detects_timestamp_ids() {
+        let directory = test_directory("detects_timestamp_ids");
+        write_files(
+            &directory,
+            &["20261018143000_a_up.sql", "20261018143000_a_down.sql"],
+        );
+
+        let rebuilt = rebuild_migrations_data(&directory).unwrap();
+        assert_eq!(rebuilt.rebuilt.id_format, IdFormat::Timestamp);
+    }
+}
@@ -0,0 +1,112 @@
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use log::debug;
+use serde::Serialize;
+use sqlx::AnyConnection;
+
+use crate::{
+    database::table_exists, derictive_constants::is_transactional, errors::Result,
+    features::execute_statements, history::timestamp, schema::Backend, util::checksum,
+};
+
+/// Directory with repeatable migrations inside of migrations directory
+pub const REPEATABLE_DIR_NAME: &str = "repeatable";
+
+/// Repeatable migration file which is new or was changed since it was applied
+#[derive(Serialize, Debug, Clone)]
+pub struct RepeatableToApply {
+    /// File name inside of repeatable directory. Database tracks files by it
+    pub name: String,
+    pub file: PathBuf,
+    pub checksum: String,
+    #[serde(skip)]
+    pub sql_code: String,
+}
+
+impl RepeatableToApply {
+    /// Returns false if file has `-- migren:no-transaction` directive
+    pub fn is_transactional(&self) -> bool {
+        is_transactional(&self.sql_code)
+    }
+}
+
+/// Checksums of applied repeatable files by file name
+async fn applied_checksums(conn: &mut AnyConnection) -> Result<HashMap<String, String>> {
+    if !table_exists(conn, "migren_repeatable").await? {
+        return Ok(HashMap::new());
+    }
+
+    let rows =
+        sqlx::query_as::<_, (String, String)>("SELECT name, checksum FROM migren_repeatable")
+            .fetch_all(conn)
+            .await?;
+    Ok(rows.into_iter().collect())
+}
+
+/// Repeatable migration files whose checksum differs from the applied one, ordered by file name
+pub async fn pending_repeatables(
+    conn: &mut AnyConnection,
+    directory: &Path,
+) -> Result<Vec<RepeatableToApply>> {
+    let repeatable_dir = directory.join(REPEATABLE_DIR_NAME);
+    if !fs::exists(&repeatable_dir)? {
+        return Ok(Vec::new());
+    }
+
+    let mut names: Vec<String> = fs::read_dir(&repeatable_dir)?
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_type().is_ok_and(|file_type| file_type.is_file()))
+        .map(|entry| entry.file_name().to_string_lossy().to_string())
+        .filter(|name| name.ends_with(".sql"))
+        .collect();
+    names.sort();
+
+    let applied = applied_checksums(conn).await?;
+    let mut pending = Vec::new();
+    for name in names {
+        let file = repeatable_dir.join(&name);
+        let sql_code = fs::read_to_string(&file)?;
+        let checksum = checksum(&sql_code);
+        if applied.get(&name) == Some(&checksum) {
+            continue;
+        }
+
+        pending.push(RepeatableToApply {
+            name,
+            file,
+            checksum,
+            sql_code,
+        });
+    }
+
+    Ok(pending)
+}
+
+/// Executes repeatable file and saves its checksum
+pub(crate) async fn apply_repeatable(
+    conn: &mut AnyConnection,
+    repeatable: &RepeatableToApply,
+) -> Result<()> {
+    execute_statements(conn, &repeatable.file, &repeatable.sql_code).await?;
+
+    sqlx::query(&Backend::of(conn).sql("DELETE FROM migren_repeatable WHERE name = $1"))
+        .bind(&repeatable.name)
+        .execute(&mut *conn)
+        .await?;
+    sqlx::query(
+        &Backend::of(conn)
+            .sql("INSERT INTO migren_repeatable (name, checksum, applied_at) VALUES ($1, $2, $3)"),
+    )
+    .bind(&repeatable.name)
+    .bind(&repeatable.checksum)
+    .bind(timestamp())
+    .execute(&mut *conn)
+    .await?;
+
+    debug!("Applied repeatable file {:?}", repeatable.file);
+    Ok(())
+}
@@ -0,0 +1,138 @@
+use std::{collections::HashSet, fs};
+
+use log::debug;
+use serde::Serialize;
+use sqlx::AnyConnection;
+
+use crate::{
+    errors::{MigrenError, Result},
+    features::{MigrationData, MigrationsData, execute_statements},
+    history::Direction,
+    schema::schema_snapshot,
+    util::{DiffLine, assert_migration_files_exists, diff_lines},
+};
+
+/// Why migration did not pass round-trip test
+#[derive(Serialize, Debug, Clone)]
+#[serde(tag = "kind", content = "details", rename_all = "snake_case")]
+pub enum MigrationTestFailure {
+    /// Migration file failed to execute
+    FileFailed { direction: Direction, error: String },
+    /// Down file did not restore schema. Diff from schema before up file to schema after down file
+    LossyDown(Vec<DiffLine>),
+    /// Up file applied again after down file gave different schema
+    ReapplyDiffers(Vec<DiffLine>),
+}
+
+/// Result of round-trip test of single migration
+#[derive(Serialize, Debug, Clone)]
+pub struct MigrationTest {
+    pub id: u64,
+    pub name: String,
+    /// `None` if migration passed
+    pub failure: Option<MigrationTestFailure>,
+}
+
+/// Changed lines of diff. Empty if texts are the same
+fn changes(old: &str, new: &str) -> Vec<DiffLine> {
+    diff_lines(old, new)
+        .into_iter()
+        .filter(|line| !matches!(line, DiffLine::Same(_)))
+        .collect()
+}
+
+/// Runs migration file, turning its error into test failure
+async fn run_file(
+    conn: &mut AnyConnection,
+    migrations_data: &MigrationsData,
+    migration: &MigrationData,
+    direction: Direction,
+) -> Result<Option<MigrationTestFailure>> {
+    let file = match direction {
+        Direction::Up => migrations_data.up_file(migration),
+        Direction::Down => migrations_data.down_file(migration),
+    };
+    let sql_code = fs::read_to_string(&file)?;
+
+    match execute_statements(conn, &file, &sql_code).await {
+        Ok(()) => Ok(None),
+        Err(err) => {
+            let error = match std::error::Error::source(&err) {
+                Some(source) => format!("{err}: {source}"),
+                None => err.to_string(),
+            };
+            Ok(Some(MigrationTestFailure::FileFailed { direction, error }))
+        }
+    }
+}
+
+/// Tests one migration: applies up file, reverts it with down file and applies up file again,
+/// comparing schema after every step
+async fn test_migration(
+    conn: &mut AnyConnection,
+    migrations_data: &MigrationsData,
+    migration: &MigrationData,
+) -> Result<Option<MigrationTestFailure>> {
+    let before_up = schema_snapshot(conn).await?;
+    if let Some(failure) = run_file(conn, migrations_data, migration, Direction::Up).await? {
+        return Ok(Some(failure));
+    }
+    let after_up = schema_snapshot(conn).await?;
+
+    if let Some(failure) = run_file(conn, migrations_data, migration, Direction::Down).await? {
+        return Ok(Some(failure));
+    }
+    let after_down = schema_snapshot(conn).await?;
+    let lossy = changes(&before_up, &after_down);
+    if !lossy.is_empty() {
+        return Ok(Some(MigrationTestFailure::LossyDown(lossy)));
+    }
+
+    if let Some(failure) = run_file(conn, migrations_data, migration, Direction::Up).await? {
+        return Ok(Some(failure));
+    }
+    let reapplied = changes(&after_up, &schema_snapshot(conn).await?);
+    if !reapplied.is_empty() {
+        return Ok(Some(MigrationTestFailure::ReapplyDiffers(reapplied)));
+    }
+
+    Ok(None)
+}
+
+/// Round-trip tests every migration on empty scratch database, in order of applying.
+/// Stops at the first failed migration, because the following ones depend on it
+pub async fn test_migrations(
+    conn: &mut AnyConnection,
+    migrations_data: &MigrationsData,
+) -> Result<Vec<MigrationTest>> {
+    if !schema_snapshot(conn).await?.is_empty() {
+        return Err(MigrenError::ScratchDatabaseNotEmpty);
+    }
+
+    let ids: HashSet<u64> = migrations_data.migrations.iter().map(|m| m.id).collect();
+    let mut tests = Vec::new();
+    // Skipping 0 migration
+    for id in migrations_data
+        .topological_order(&ids)?
+        .into_iter()
+        .filter(|id| *id != 0)
+    {
+        let migration = migrations_data.migration_by_id(id).unwrap();
+        assert_migration_files_exists(&migrations_data.directory, migration)?;
+
+        debug!("Testing migration {id}");
+        let failure = test_migration(conn, migrations_data, migration).await?;
+        let failed = failure.is_some();
+        tests.push(MigrationTest {
+            id,
+            name: migration.name.clone(),
+            failure,
+        });
+
+        if failed {
+            break;
+        }
+    }
+
+    Ok(tests)
+}
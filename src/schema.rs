@@ -0,0 +1,478 @@
+use std::{
+    borrow::Cow,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use regex::Regex;
+use sqlx::AnyConnection;
+
+use crate::{
+    errors::Result,
+    util::{DiffLine, diff_lines},
+};
+
+/// File with schema snapshot, next to `.migren.json`
+pub const SCHEMA_FILE_NAME: &str = "schema.sql";
+
+const SCHEMA_FILE_HEADER: &str =
+    "-- Database schema written by migren after migrations. Do not edit it manually";
+
+const SQLITE_TABLES: &str = "
+SELECT name, sql
+FROM sqlite_master
+WHERE type = 'table' AND name NOT LIKE 'sqlite\\_%' ESCAPE '\\' AND name NOT LIKE 'migren\\_%' ESCAPE '\\'
+ORDER BY name
+";
+
+const SQLITE_COLUMNS: &str = "
+SELECT m.name, p.name, TRIM(p.type || CASE WHEN p.\"notnull\" THEN ' NOT NULL' ELSE '' END || COALESCE(' DEFAULT ' || p.dflt_value, ''))
+FROM sqlite_master m, pragma_table_info(m.name) p
+WHERE m.type = 'table' AND m.name NOT LIKE 'sqlite\\_%' ESCAPE '\\' AND m.name NOT LIKE 'migren\\_%' ESCAPE '\\'
+ORDER BY m.name, p.cid
+";
+
+// SQLite constraints have no names, definition is used as name
+const SQLITE_CONSTRAINTS: &str = "
+WITH tables AS (
+    SELECT name FROM sqlite_master
+    WHERE type = 'table' AND name NOT LIKE 'sqlite\\_%' ESCAPE '\\' AND name NOT LIKE 'migren\\_%' ESCAPE '\\'
+),
+constraints AS (
+    SELECT t.name AS table_name, 'PRIMARY KEY (' || (
+        SELECT GROUP_CONCAT(name, ', ') FROM (SELECT p.name FROM pragma_table_info(t.name) p WHERE p.pk > 0 ORDER BY p.pk)
+    ) || ')' AS definition
+    FROM tables t
+    WHERE EXISTS (SELECT 1 FROM pragma_table_info(t.name) p WHERE p.pk > 0)
+    UNION ALL
+    SELECT t.name, 'UNIQUE (' || GROUP_CONCAT(i.name, ', ') || ')'
+    FROM tables t, pragma_index_list(t.name) l, pragma_index_info(l.name) i
+    WHERE l.origin = 'u'
+    GROUP BY t.name, l.name
+    UNION ALL
+    SELECT t.name, 'FOREIGN KEY (' || GROUP_CONCAT(f.\"from\", ', ') || ') REFERENCES ' || f.\"table\"
+        || CASE WHEN MAX(f.\"to\") IS NULL THEN '' ELSE ' (' || GROUP_CONCAT(f.\"to\", ', ') || ')' END
+    FROM tables t, pragma_foreign_key_list(t.name) f
+    GROUP BY t.name, f.id
+)
+SELECT table_name, definition, definition FROM constraints ORDER BY table_name, definition
+";
+
+const SQLITE_OBJECTS: &str = "
+SELECT type, name, tbl_name, sql || ';'
+FROM sqlite_master
+WHERE type IN ('index', 'view', 'trigger') AND sql IS NOT NULL
+    AND name NOT LIKE 'sqlite\\_%' ESCAPE '\\' AND tbl_name NOT LIKE 'migren\\_%' ESCAPE '\\'
+ORDER BY name
+";
+
+const POSTGRES_TABLES: &str = "
+SELECT CAST(table_name AS TEXT), CAST('' AS TEXT)
+FROM information_schema.tables
+WHERE table_schema = current_schema() AND table_type = 'BASE TABLE' AND table_name NOT LIKE 'migren\\_%'
+ORDER BY table_name
+";
+
+const POSTGRES_COLUMNS: &str = "
+SELECT CAST(table_name AS TEXT), CAST(column_name AS TEXT), CONCAT(
+    CASE
+        WHEN data_type IN ('USER-DEFINED', 'ARRAY') THEN udt_name
+        WHEN character_maximum_length IS NOT NULL THEN CONCAT(data_type, '(', character_maximum_length, ')')
+        WHEN data_type = 'numeric' AND numeric_precision IS NOT NULL THEN CONCAT(data_type, '(', numeric_precision, ',', numeric_scale, ')')
+        ELSE data_type
+    END,
+    CASE WHEN is_nullable = 'NO' THEN ' NOT NULL' ELSE '' END, COALESCE(' DEFAULT ' || column_default, ''))
+FROM information_schema.columns
+WHERE table_schema = current_schema() AND table_name NOT LIKE 'migren\\_%'
+    AND table_name IN (SELECT table_name FROM information_schema.tables WHERE table_schema = current_schema() AND table_type = 'BASE TABLE')
+ORDER BY table_name, ordinal_position
+";
+
+const POSTGRES_CONSTRAINTS: &str = "
+SELECT CAST(rel.relname AS TEXT), CAST(con.conname AS TEXT), pg_get_constraintdef(con.oid)
+FROM pg_constraint con
+JOIN pg_class rel ON rel.oid = con.conrelid
+JOIN pg_namespace ns ON ns.oid = rel.relnamespace
+WHERE ns.nspname = current_schema() AND rel.relname NOT LIKE 'migren\\_%' AND con.contype IN ('p', 'u', 'f', 'c', 'x')
+ORDER BY rel.relname, con.conname
+";
+
+// Indexes backing constraints are created by constraints
+const POSTGRES_OBJECTS: &str = "
+SELECT CAST('index' AS TEXT), CAST(indexname AS TEXT), CAST(tablename AS TEXT), CONCAT(indexdef, ';')
+FROM pg_indexes idx
+WHERE schemaname = current_schema() AND tablename NOT LIKE 'migren\\_%'
+    AND NOT EXISTS (SELECT 1 FROM pg_constraint con WHERE con.conname = idx.indexname AND con.contype IN ('p', 'u', 'x'))
+UNION ALL
+SELECT CAST('view' AS TEXT), CAST(table_name AS TEXT), CAST(table_name AS TEXT), CONCAT('CREATE VIEW ', table_name, ' AS', chr(10), RTRIM(TRIM(view_definition), ';'), ';')
+FROM information_schema.views
+WHERE table_schema = current_schema()
+ORDER BY 2
+";
+
+const MYSQL_TABLES: &str = "
+SELECT CAST(table_name AS CHAR), ''
+FROM information_schema.tables
+WHERE table_schema = DATABASE() AND table_type = 'BASE TABLE' AND table_name NOT LIKE 'migren\\_%'
+ORDER BY table_name
+";
+
+const MYSQL_COLUMNS: &str = "
+SELECT CAST(table_name AS CHAR), CAST(column_name AS CHAR), CONCAT(column_type, IF(is_nullable = 'NO', ' NOT NULL', ''), COALESCE(CONCAT(' DEFAULT ', column_default), ''), IF(extra LIKE '%auto_increment%', ' AUTO_INCREMENT', ''))
+FROM information_schema.columns
+WHERE table_schema = DATABASE() AND table_name NOT LIKE 'migren\\_%'
+    AND table_name IN (SELECT table_name FROM information_schema.tables WHERE table_schema = DATABASE() AND table_type = 'BASE TABLE')
+ORDER BY table_name, ordinal_position
+";
+
+const MYSQL_CONSTRAINTS: &str = "
+SELECT CAST(tc.table_name AS CHAR), CAST(tc.constraint_name AS CHAR), CONCAT(tc.constraint_type,
+    CASE WHEN tc.constraint_type = 'CHECK' THEN CONCAT(' ', MAX(cc.check_clause))
+        ELSE CONCAT(' (', GROUP_CONCAT(kcu.column_name ORDER BY kcu.ordinal_position SEPARATOR ', '), ')') END,
+    IF(tc.constraint_type = 'FOREIGN KEY', CONCAT(' REFERENCES ', MAX(kcu.referenced_table_name), ' (', GROUP_CONCAT(kcu.referenced_column_name ORDER BY kcu.ordinal_position SEPARATOR ', '), ')'), ''))
+FROM information_schema.table_constraints tc
+LEFT JOIN information_schema.key_column_usage kcu
+    ON kcu.constraint_schema = tc.constraint_schema AND kcu.table_name = tc.table_name AND kcu.constraint_name = tc.constraint_name
+LEFT JOIN information_schema.check_constraints cc
+    ON cc.constraint_schema = tc.constraint_schema AND cc.constraint_name = tc.constraint_name
+WHERE tc.table_schema = DATABASE() AND tc.table_name NOT LIKE 'migren\\_%'
+GROUP BY tc.table_name, tc.constraint_name, tc.constraint_type
+ORDER BY tc.table_name, tc.constraint_name
+";
+
+// Indexes backing constraints are created by constraints
+const MYSQL_OBJECTS: &str = "
+SELECT 'index', CAST(index_name AS CHAR), CAST(table_name AS CHAR),
+    CONCAT('CREATE ', IF(non_unique = 0, 'UNIQUE ', ''), 'INDEX ', index_name, ' ON ', table_name, ' (', GROUP_CONCAT(column_name ORDER BY seq_in_index SEPARATOR ', '), ');')
+FROM information_schema.statistics st
+WHERE table_schema = DATABASE() AND table_name NOT LIKE 'migren\\_%'
+    AND index_name NOT IN (SELECT constraint_name FROM information_schema.table_constraints tc WHERE tc.table_schema = DATABASE() AND tc.table_name = st.table_name)
+GROUP BY table_name, index_name, non_unique
+UNION ALL
+SELECT 'view', CAST(table_name AS CHAR), CAST(table_name AS CHAR), CONCAT('CREATE VIEW ', table_name, ' AS ', view_definition, ';')
+FROM information_schema.views
+WHERE table_schema = DATABASE()
+ORDER BY 2
+";
+
+/// Database kind schema was read from. Decides SQL dialect of schema statements
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backend {
+    Sqlite,
+    Postgres,
+    MySql,
+}
+
+impl Backend {
+    /// Query written with `$1, $2` placeholders, in placeholder style of backend.
+    /// `Any` driver passes placeholders as they are, and MySQL takes only `?`
+    pub fn sql(self, query: &str) -> Cow<'_, str> {
+        match self {
+            Backend::MySql => Regex::new(r"\$\d+").unwrap().replace_all(query, "?"),
+            _ => Cow::Borrowed(query),
+        }
+    }
+
+    pub fn of(conn: &AnyConnection) -> Self {
+        match conn.backend_name() {
+            "PostgreSQL" => Backend::Postgres,
+            "MySQL" => Backend::MySql,
+            _ => Backend::Sqlite,
+        }
+    }
+
+    /// Backend of database url, recognised by its scheme like driver is
+    pub fn from_url(url: &str) -> Self {
+        match url.split(':').next().unwrap_or_default() {
+            "postgres" | "postgresql" => Backend::Postgres,
+            "mysql" | "mariadb" => Backend::MySql,
+            _ => Backend::Sqlite,
+        }
+    }
+}
+
+/// Schema queries of backend. Tables return name and create statement (SQLite only),
+/// columns and constraints return table, name and definition,
+/// objects return type, name, table and create statement
+struct SchemaQueries {
+    tables: &'static str,
+    columns: &'static str,
+    constraints: &'static str,
+    objects: &'static str,
+}
+
+const SQLITE_QUERIES: SchemaQueries = SchemaQueries {
+    tables: SQLITE_TABLES,
+    columns: SQLITE_COLUMNS,
+    constraints: SQLITE_CONSTRAINTS,
+    objects: SQLITE_OBJECTS,
+};
+
+const POSTGRES_QUERIES: SchemaQueries = SchemaQueries {
+    tables: POSTGRES_TABLES,
+    columns: POSTGRES_COLUMNS,
+    constraints: POSTGRES_CONSTRAINTS,
+    objects: POSTGRES_OBJECTS,
+};
+
+const MYSQL_QUERIES: SchemaQueries = SchemaQueries {
+    tables: MYSQL_TABLES,
+    columns: MYSQL_COLUMNS,
+    constraints: MYSQL_CONSTRAINTS,
+    objects: MYSQL_OBJECTS,
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Column {
+    pub name: String,
+    /// Type, nullability and default
+    pub definition: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Constraint {
+    pub name: String,
+    /// Constraint as written in `ALTER TABLE ... ADD CONSTRAINT name`
+    pub definition: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Table {
+    pub name: String,
+    pub columns: Vec<Column>,
+    pub constraints: Vec<Constraint>,
+    /// Create statement saved by database. Only SQLite keeps it
+    pub sql: Option<String>,
+}
+
+/// Index, view or trigger created by single statement
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SchemaObject {
+    pub name: String,
+    /// Table index or trigger belongs to. Name of view for views
+    pub table: String,
+    /// Create statement
+    pub sql: String,
+}
+
+/// Database schema read from database by [`read_schema`]
+#[derive(Debug, Clone)]
+pub struct DatabaseSchema {
+    pub backend: Backend,
+    pub tables: Vec<Table>,
+    pub indexes: Vec<SchemaObject>,
+    pub views: Vec<SchemaObject>,
+    pub triggers: Vec<SchemaObject>,
+}
+
+impl DatabaseSchema {
+    pub fn is_empty(&self) -> bool {
+        self.tables.is_empty()
+            && self.indexes.is_empty()
+            && self.views.is_empty()
+            && self.triggers.is_empty()
+    }
+
+    pub fn table(&self, name: &str) -> Option<&Table> {
+        self.tables.iter().find(|table| table.name == name)
+    }
+
+    /// Statement creating table. Constraints are added separately unless database kept create statement
+    pub fn create_table_sql(&self, table: &Table) -> String {
+        if let Some(sql) = &table.sql {
+            return format!("{sql};");
+        }
+
+        let columns: Vec<String> = table
+            .columns
+            .iter()
+            .map(|column| format!("    {} {}", column.name, column.definition))
+            .collect();
+        format!("CREATE TABLE {} (\n{}\n);", table.name, columns.join(",\n"))
+    }
+
+    /// Statement adding constraint to existing table. `None` for SQLite, which can not do it
+    pub fn add_constraint_sql(&self, table: &str, constraint: &Constraint) -> Option<String> {
+        match self.backend {
+            Backend::Sqlite => None,
+            // MySQL primary key is always named PRIMARY and name can not be set
+            Backend::MySql if constraint.definition.starts_with("PRIMARY KEY") => Some(format!(
+                "ALTER TABLE {table} ADD {};",
+                constraint.definition
+            )),
+            _ => Some(format!(
+                "ALTER TABLE {table} ADD CONSTRAINT {} {};",
+                constraint.name, constraint.definition
+            )),
+        }
+    }
+
+    /// Normalized schema text: tables with columns, constraints, indexes, views and triggers,
+    /// one statement per object in deterministic order
+    pub fn to_sql(&self) -> String {
+        let mut statements: Vec<String> = self
+            .tables
+            .iter()
+            .map(|table| self.create_table_sql(table))
+            .collect();
+
+        for table in self.tables.iter().filter(|table| table.sql.is_none()) {
+            statements.extend(
+                table
+                    .constraints
+                    .iter()
+                    .filter_map(|constraint| self.add_constraint_sql(&table.name, constraint)),
+            );
+        }
+
+        for object in self.indexes.iter().chain(&self.views).chain(&self.triggers) {
+            statements.push(object.sql.clone());
+        }
+
+        let statements: Vec<String> = statements
+            .iter()
+            .map(|statement| {
+                statement
+                    .lines()
+                    .map(str::trim_end)
+                    .collect::<Vec<_>>()
+                    .join("\n")
+            })
+            .collect();
+        statements.join("\n\n")
+    }
+}
+
+/// Reads tables with columns and constraints, indexes, views and triggers from database.
+/// Uses `sqlite_master` for SQLite and `information_schema` for Postgres and MySQL.
+/// Migren tables are skipped
+pub async fn read_schema(conn: &mut AnyConnection) -> Result<DatabaseSchema> {
+    let backend = Backend::of(conn);
+    let queries = match backend {
+        Backend::Sqlite => SQLITE_QUERIES,
+        Backend::Postgres => POSTGRES_QUERIES,
+        Backend::MySql => MYSQL_QUERIES,
+    };
+
+    let mut tables: Vec<Table> = sqlx::query_as::<_, (String, Option<String>)>(queries.tables)
+        .fetch_all(&mut *conn)
+        .await?
+        .into_iter()
+        .map(|(name, sql)| Table {
+            name,
+            columns: Vec::new(),
+            constraints: Vec::new(),
+            sql: sql.filter(|sql| !sql.is_empty()),
+        })
+        .collect();
+
+    let columns = sqlx::query_as::<_, (String, String, String)>(queries.columns)
+        .fetch_all(&mut *conn)
+        .await?;
+    let constraints = sqlx::query_as::<_, (String, String, String)>(queries.constraints)
+        .fetch_all(&mut *conn)
+        .await?;
+    for table in &mut tables {
+        table.columns = columns
+            .iter()
+            .filter(|(table_name, _, _)| *table_name == table.name)
+            .map(|(_, name, definition)| Column {
+                name: name.clone(),
+                definition: definition.clone(),
+            })
+            .collect();
+        table.constraints = constraints
+            .iter()
+            .filter(|(table_name, _, _)| *table_name == table.name)
+            .map(|(_, name, definition)| Constraint {
+                name: name.clone(),
+                definition: definition.clone(),
+            })
+            .collect();
+    }
+
+    let mut schema = DatabaseSchema {
+        backend,
+        tables,
+        indexes: Vec::new(),
+        views: Vec::new(),
+        triggers: Vec::new(),
+    };
+    let objects = sqlx::query_as::<_, (String, String, String, String)>(queries.objects)
+        .fetch_all(&mut *conn)
+        .await?;
+    for (object_type, name, table, sql) in objects {
+        let object = SchemaObject { name, table, sql };
+        match object_type.as_str() {
+            "index" => schema.indexes.push(object),
+            "view" => schema.views.push(object),
+            _ => schema.triggers.push(object),
+        }
+    }
+
+    Ok(schema)
+}
+
+/// Normalized text of database schema, see [`DatabaseSchema::to_sql`].
+/// Used to compare schema before and after migrations and for `schema.sql`
+pub async fn schema_snapshot(conn: &mut AnyConnection) -> Result<String> {
+    Ok(read_schema(conn).await?.to_sql())
+}
+
+/// Path of schema file in migrations directory
+pub fn schema_file(directory: &Path) -> PathBuf {
+    directory.join(SCHEMA_FILE_NAME)
+}
+
+/// Content of schema file for schema snapshot
+fn schema_file_content(snapshot: &str) -> String {
+    match snapshot.is_empty() {
+        true => format!("{SCHEMA_FILE_HEADER}\n"),
+        false => format!("{SCHEMA_FILE_HEADER}\n\n{snapshot}\n"),
+    }
+}
+
+/// Writes current database schema into `schema.sql` of migrations directory
+pub async fn write_schema_file(conn: &mut AnyConnection, directory: &Path) -> Result<PathBuf> {
+    let path = schema_file(directory);
+    let content = schema_file_content(&schema_snapshot(conn).await?);
+    fs::write(&path, content)?;
+
+    Ok(path)
+}
+
+/// Diff from `schema.sql` of migrations directory to current database schema.
+/// Missing file is compared as empty one
+pub async fn schema_file_diff(conn: &mut AnyConnection, directory: &Path) -> Result<Vec<DiffLine>> {
+    let path = schema_file(directory);
+    let saved = match fs::exists(&path)? {
+        true => fs::read_to_string(&path)?,
+        false => String::new(),
+    };
+    let current = schema_file_content(&schema_snapshot(conn).await?);
+
+    Ok(diff_lines(&saved, &current)
+        .into_iter()
+        .filter(|line| !matches!(line, DiffLine::Same(_)))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn writes_placeholders_of_backend() {
+        let query = "INSERT INTO migren_checksums (migration_id, checksum) VALUES ($1, $2)";
+        assert_eq!(
+            Backend::MySql.sql(query),
+            "INSERT INTO migren_checksums (migration_id, checksum) VALUES (?, ?)"
+        );
+        assert_eq!(Backend::Postgres.sql(query), query);
+        assert_eq!(Backend::Sqlite.sql(query), query);
+        assert_eq!(
+            Backend::MySql.sql("UPDATE migren_data SET last_migration_applied = $10"),
+            "UPDATE migren_data SET last_migration_applied = ?"
+        );
+    }
+}
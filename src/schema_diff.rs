@@ -0,0 +1,561 @@
+use std::collections::{BTreeMap, HashSet};
+
+use sqlx::AnyConnection;
+
+use crate::{
+    errors::Result,
+    features::MigrationData,
+    schema::{Backend, Column, Constraint, DatabaseSchema, SchemaObject, Table},
+};
+
+/// Migration created from difference between migrations schema and target schema
+#[derive(Debug, Clone)]
+pub struct GeneratedMigration {
+    pub migration: MigrationData,
+    /// Changes which could not be written as statements. Files have TODO comments for them
+    pub unsupported: Vec<String>,
+}
+
+/// Statements moving schema to another one
+#[derive(Debug, Clone, Default)]
+pub struct SchemaChanges {
+    /// Statements and TODO comments for unsupported changes, in order of execution
+    pub statements: Vec<String>,
+    /// Changes which could not be written as statements
+    pub unsupported: Vec<String>,
+}
+
+impl SchemaChanges {
+    pub fn is_empty(&self) -> bool {
+        self.statements.is_empty()
+    }
+
+    pub(crate) fn push(&mut self, statement: String) {
+        self.statements.push(statement);
+    }
+
+    pub(crate) fn unsupported(&mut self, change: String) {
+        self.statements.push(format!("-- TODO: {change}"));
+        self.unsupported.push(change);
+    }
+
+    /// Statements as migration file body
+    pub fn to_sql(&self) -> String {
+        self.statements.join("\n")
+    }
+}
+
+fn by_name<T>(items: &[T], name: impl Fn(&T) -> &str) -> BTreeMap<&str, &T> {
+    items.iter().map(|item| (name(item), item)).collect()
+}
+
+/// Objects of `from` missing or changed in `to`, and objects of `to` missing or changed in `from`
+fn changed_objects<'a>(
+    from: &'a [SchemaObject],
+    to: &'a [SchemaObject],
+) -> (Vec<&'a SchemaObject>, Vec<&'a SchemaObject>) {
+    let from_objects = by_name(from, |object| &object.name);
+    let to_objects = by_name(to, |object| &object.name);
+
+    let removed = from
+        .iter()
+        .filter(|object| to_objects.get(object.name.as_str()) != Some(object))
+        .collect();
+    let added = to
+        .iter()
+        .filter(|object| from_objects.get(object.name.as_str()) != Some(object))
+        .collect();
+    (removed, added)
+}
+
+fn drop_index_sql(backend: Backend, index: &SchemaObject) -> String {
+    match backend {
+        Backend::MySql => format!("DROP INDEX {} ON {};", index.name, index.table),
+        _ => format!("DROP INDEX {};", index.name),
+    }
+}
+
+fn drop_trigger_sql(backend: Backend, trigger: &SchemaObject) -> String {
+    match backend {
+        Backend::Postgres => format!("DROP TRIGGER {} ON {};", trigger.name, trigger.table),
+        _ => format!("DROP TRIGGER {};", trigger.name),
+    }
+}
+
+fn drop_constraint_sql(backend: Backend, table: &str, constraint: &Constraint) -> Option<String> {
+    let definition = constraint.definition.as_str();
+    match backend {
+        Backend::Sqlite => None,
+        Backend::MySql if definition.starts_with("PRIMARY KEY") => {
+            Some(format!("ALTER TABLE {table} DROP PRIMARY KEY;"))
+        }
+        Backend::MySql if definition.starts_with("FOREIGN KEY") => Some(format!(
+            "ALTER TABLE {table} DROP FOREIGN KEY {};",
+            constraint.name
+        )),
+        Backend::MySql if definition.starts_with("UNIQUE") => Some(format!(
+            "ALTER TABLE {table} DROP INDEX {};",
+            constraint.name
+        )),
+        Backend::MySql => Some(format!(
+            "ALTER TABLE {table} DROP CHECK {};",
+            constraint.name
+        )),
+        Backend::Postgres => Some(format!(
+            "ALTER TABLE {table} DROP CONSTRAINT {};",
+            constraint.name
+        )),
+    }
+}
+
+/// Columns of `from` table missing in `to`, columns of `to` missing in `from`
+/// and columns present in both with changed definition
+fn changed_columns<'a>(
+    from: &'a Table,
+    to: &'a Table,
+) -> (
+    Vec<&'a Column>,
+    Vec<&'a Column>,
+    Vec<(&'a Column, &'a Column)>,
+) {
+    let from_columns = by_name(&from.columns, |column| &column.name);
+    let to_columns = by_name(&to.columns, |column| &column.name);
+
+    let removed = from
+        .columns
+        .iter()
+        .filter(|column| !to_columns.contains_key(column.name.as_str()))
+        .collect();
+    let added = to
+        .columns
+        .iter()
+        .filter(|column| !from_columns.contains_key(column.name.as_str()))
+        .collect();
+    let changed = from
+        .columns
+        .iter()
+        .filter_map(|column| {
+            to_columns
+                .get(column.name.as_str())
+                .filter(|new| new.definition != column.definition)
+                .map(|new| (column, *new))
+        })
+        .collect();
+    (removed, added, changed)
+}
+
+/// Constraints of `from` table missing or changed in `to` and the other way around
+fn changed_constraints<'a>(
+    from: &'a Table,
+    to: &'a Table,
+) -> (Vec<&'a Constraint>, Vec<&'a Constraint>) {
+    let removed = from
+        .constraints
+        .iter()
+        .filter(|constraint| !to.constraints.contains(constraint))
+        .collect();
+    let added = to
+        .constraints
+        .iter()
+        .filter(|constraint| !from.constraints.contains(constraint))
+        .collect();
+    (removed, added)
+}
+
+/// Statements moving database from `from` schema to `to` schema: dropping removed objects
+/// first and creating added ones after. Changes which can not be written as statements,
+/// like changed column definitions or constraints of existing SQLite tables, become TODO comments
+pub fn diff_schemas(from: &DatabaseSchema, to: &DatabaseSchema) -> SchemaChanges {
+    let backend = to.backend;
+    let mut changes = SchemaChanges::default();
+
+    let (removed_views, added_views) = changed_objects(&from.views, &to.views);
+    let (removed_triggers, added_triggers) = changed_objects(&from.triggers, &to.triggers);
+    let (removed_indexes, added_indexes) = changed_objects(&from.indexes, &to.indexes);
+    let kept_tables: Vec<(&Table, &Table)> = from
+        .tables
+        .iter()
+        .filter_map(|table| to.table(&table.name).map(|new| (table, new)))
+        .collect();
+    let removed_tables: HashSet<&str> = from
+        .tables
+        .iter()
+        .filter(|table| to.table(&table.name).is_none())
+        .map(|table| table.name.as_str())
+        .collect();
+
+    for view in removed_views {
+        changes.push(format!("DROP VIEW {};", view.name));
+    }
+    for trigger in removed_triggers {
+        changes.push(drop_trigger_sql(backend, trigger));
+    }
+    // Indexes of dropped tables are dropped with them
+    for index in removed_indexes
+        .into_iter()
+        .filter(|index| !removed_tables.contains(index.table.as_str()))
+    {
+        changes.push(drop_index_sql(backend, index));
+    }
+
+    for (table, new_table) in &kept_tables {
+        for constraint in changed_constraints(table, new_table).0 {
+            match drop_constraint_sql(backend, &table.name, constraint) {
+                Some(statement) => changes.push(statement),
+                None => changes.unsupported(format!(
+                    "drop constraint `{}` of table {}. SQLite can not change constraints of existing table",
+                    constraint.definition, table.name
+                )),
+            }
+        }
+    }
+
+    for (table, new_table) in &kept_tables {
+        let (removed, _, changed) = changed_columns(table, new_table);
+        for column in removed {
+            changes.push(format!(
+                "ALTER TABLE {} DROP COLUMN {};",
+                table.name, column.name
+            ));
+        }
+        for (column, new_column) in changed {
+            changes.unsupported(format!(
+                "column {}.{} changed from `{}` to `{}`",
+                table.name, column.name, column.definition, new_column.definition
+            ));
+        }
+    }
+
+    for table in from
+        .tables
+        .iter()
+        .filter(|table| removed_tables.contains(table.name.as_str()))
+    {
+        changes.push(format!("DROP TABLE {};", table.name));
+    }
+
+    let added_tables: Vec<&Table> = to
+        .tables
+        .iter()
+        .filter(|table| from.table(&table.name).is_none())
+        .collect();
+    for table in &added_tables {
+        changes.push(to.create_table_sql(table));
+    }
+
+    for (table, new_table) in &kept_tables {
+        for column in changed_columns(table, new_table).1 {
+            changes.push(format!(
+                "ALTER TABLE {} ADD COLUMN {} {};",
+                table.name, column.name, column.definition
+            ));
+        }
+    }
+
+    // Tables created from saved create statement already have their constraints
+    for table in added_tables.iter().filter(|table| table.sql.is_none()) {
+        for constraint in &table.constraints {
+            if let Some(statement) = to.add_constraint_sql(&table.name, constraint) {
+                changes.push(statement);
+            }
+        }
+    }
+    for (table, new_table) in &kept_tables {
+        for constraint in changed_constraints(table, new_table).1 {
+            match to.add_constraint_sql(&table.name, constraint) {
+                Some(statement) => changes.push(statement),
+                None => changes.unsupported(format!(
+                    "add constraint `{}` to table {}. SQLite can not change constraints of existing table",
+                    constraint.definition, table.name
+                )),
+            }
+        }
+    }
+
+    for object in added_indexes
+        .into_iter()
+        .chain(added_views)
+        .chain(added_triggers)
+    {
+        changes.push(object.sql.clone());
+    }
+
+    changes
+}
+
+/// Drops every table and view of schema and migren tables, so scratch database can be reused
+pub async fn clear_database(conn: &mut AnyConnection, schema: &DatabaseSchema) -> Result<()> {
+    let mut statements: Vec<String> = schema
+        .views
+        .iter()
+        .map(|view| format!("DROP VIEW {}", view.name))
+        .collect();
+
+    let tables = schema
+        .tables
+        .iter()
+        .map(|table| table.name.as_str())
+        .chain([
+            "migren_data",
+            "migren_checksums",
+            "migren_environment",
+            "migren_history",
+            "migren_in_progress",
+            "migren_lock",
+            "migren_repeatable",
+            "migren_seeds",
+        ]);
+    match schema.backend {
+        Backend::Postgres => {
+            statements.extend(tables.map(|table| format!("DROP TABLE IF EXISTS {table} CASCADE")))
+        }
+        Backend::MySql => {
+            statements.push("SET FOREIGN_KEY_CHECKS = 0".to_string());
+            statements.extend(tables.map(|table| format!("DROP TABLE IF EXISTS {table}")));
+            statements.push("SET FOREIGN_KEY_CHECKS = 1".to_string());
+        }
+        Backend::Sqlite => {
+            statements.push("PRAGMA foreign_keys = OFF".to_string());
+            statements.extend(tables.map(|table| format!("DROP TABLE IF EXISTS {table}")));
+            statements.push("PRAGMA foreign_keys = ON".to_string());
+        }
+    }
+
+    for statement in statements {
+        sqlx::query(&statement).execute(&mut *conn).await?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn schema(backend: Backend, tables: Vec<Table>) -> DatabaseSchema {
+        DatabaseSchema {
+            backend,
+            tables,
+            indexes: Vec::new(),
+            views: Vec::new(),
+            triggers: Vec::new(),
+        }
+    }
+
+    fn table(name: &str, columns: &[(&str, &str)], constraints: &[(&str, &str)]) -> Table {
+        Table {
+            name: name.to_string(),
+            columns: columns
+                .iter()
+                .map(|(name, definition)| Column {
+                    name: name.to_string(),
+                    definition: definition.to_string(),
+                })
+                .collect(),
+            constraints: constraints
+                .iter()
+                .map(|(name, definition)| Constraint {
+                    name: name.to_string(),
+                    definition: definition.to_string(),
+                })
+                .collect(),
+            sql: None,
+        }
+    }
+
+    fn object(name: &str, table: &str, sql: &str) -> SchemaObject {
+        SchemaObject {
+            name: name.to_string(),
+            table: table.to_string(),
+            sql: sql.to_string(),
+        }
+    }
+
+    fn users() -> Table {
+        table(
+            "users",
+            &[("id", "INTEGER NOT NULL"), ("name", "TEXT")],
+            &[("users_pkey", "PRIMARY KEY (id)")],
+        )
+    }
+
+    #[test]
+    fn same_schemas_have_no_changes() {
+        let from = schema(Backend::Postgres, vec![users()]);
+        let changes = diff_schemas(&from, &from.clone());
+        assert!(changes.is_empty());
+        assert!(changes.unsupported.is_empty());
+    }
+
+    #[test]
+    fn creates_added_table_with_constraints() {
+        let from = schema(Backend::Postgres, Vec::new());
+        let mut to = schema(Backend::Postgres, vec![users()]);
+        to.indexes.push(object(
+            "users_name",
+            "users",
+            "CREATE INDEX users_name ON users (name);",
+        ));
+
+        let changes = diff_schemas(&from, &to);
+        assert_eq!(
+            changes.statements,
+            [
+                "CREATE TABLE users (\n    id INTEGER NOT NULL,\n    name TEXT\n);",
+                "ALTER TABLE users ADD CONSTRAINT users_pkey PRIMARY KEY (id);",
+                "CREATE INDEX users_name ON users (name);",
+            ]
+        );
+    }
+
+    #[test]
+    fn drops_removed_table_without_its_indexes() {
+        let mut from = schema(Backend::Postgres, vec![users()]);
+        from.indexes.push(object(
+            "users_name",
+            "users",
+            "CREATE INDEX users_name ON users (name);",
+        ));
+        from.views.push(object(
+            "names",
+            "names",
+            "CREATE VIEW names AS SELECT name FROM users;",
+        ));
+        let to = schema(Backend::Postgres, Vec::new());
+
+        let changes = diff_schemas(&from, &to);
+        assert_eq!(
+            changes.statements,
+            ["DROP VIEW names;", "DROP TABLE users;"]
+        );
+    }
+
+    #[test]
+    fn alters_columns_of_kept_table() {
+        let from = schema(Backend::Postgres, vec![users()]);
+        let to = schema(
+            Backend::Postgres,
+            vec![table(
+                "users",
+                &[("id", "BIGINT NOT NULL"), ("email", "TEXT")],
+                &[("users_pkey", "PRIMARY KEY (id)")],
+            )],
+        );
+
+        let changes = diff_schemas(&from, &to);
+        assert_eq!(
+            changes.statements,
+            [
+                "ALTER TABLE users DROP COLUMN name;",
+                "-- TODO: column users.id changed from `INTEGER NOT NULL` to `BIGINT NOT NULL`",
+                "ALTER TABLE users ADD COLUMN email TEXT;",
+            ]
+        );
+        assert_eq!(changes.unsupported.len(), 1);
+    }
+
+    #[test]
+    fn changes_constraints_by_backend() {
+        let new_users = table(
+            "users",
+            &[("id", "INTEGER NOT NULL"), ("name", "TEXT")],
+            &[("users_name_key", "UNIQUE (name)")],
+        );
+        let cases = [
+            (
+                Backend::Postgres,
+                vec![
+                    "ALTER TABLE users DROP CONSTRAINT users_pkey;",
+                    "ALTER TABLE users ADD CONSTRAINT users_name_key UNIQUE (name);",
+                ],
+            ),
+            (
+                Backend::MySql,
+                vec![
+                    "ALTER TABLE users DROP PRIMARY KEY;",
+                    "ALTER TABLE users ADD CONSTRAINT users_name_key UNIQUE (name);",
+                ],
+            ),
+        ];
+        for (backend, expected) in cases {
+            let from = schema(backend, vec![users()]);
+            let to = schema(backend, vec![new_users.clone()]);
+            assert_eq!(diff_schemas(&from, &to).statements, expected, "{backend:?}");
+        }
+
+        let from = schema(Backend::Sqlite, vec![users()]);
+        let to = schema(Backend::Sqlite, vec![new_users]);
+        let changes = diff_schemas(&from, &to);
+        assert_eq!(changes.unsupported.len(), 2);
+        assert!(
+            changes
+                .statements
+                .iter()
+                .all(|statement| statement.starts_with("-- TODO: "))
+        );
+    }
+
+    #[test]
+    fn drops_indexes_and_triggers_by_backend() {
+        let cases = [
+            (
+                Backend::Postgres,
+                [
+                    "DROP TRIGGER users_audit ON users;",
+                    "DROP INDEX users_name;",
+                ],
+            ),
+            (
+                Backend::MySql,
+                [
+                    "DROP TRIGGER users_audit;",
+                    "DROP INDEX users_name ON users;",
+                ],
+            ),
+            (
+                Backend::Sqlite,
+                ["DROP TRIGGER users_audit;", "DROP INDEX users_name;"],
+            ),
+        ];
+        for (backend, expected) in cases {
+            let mut from = schema(backend, vec![users()]);
+            from.indexes.push(object(
+                "users_name",
+                "users",
+                "CREATE INDEX users_name ON users (name);",
+            ));
+            from.triggers.push(object(
+                "users_audit",
+                "users",
+                "CREATE TRIGGER users_audit AFTER INSERT ON users BEGIN SELECT 1; END;",
+            ));
+            let to = schema(backend, vec![users()]);
+
+            assert_eq!(diff_schemas(&from, &to).statements, expected, "{backend:?}");
+        }
+    }
+
+    #[test]
+    fn recreates_changed_view() {
+        let mut from = schema(Backend::Sqlite, vec![users()]);
+        from.views.push(object(
+            "names",
+            "names",
+            "CREATE VIEW names AS SELECT name FROM users;",
+        ));
+        let mut to = schema(Backend::Sqlite, vec![users()]);
+        to.views.push(object(
+            "names",
+            "names",
+            "CREATE VIEW names AS SELECT id, name FROM users;",
+        ));
+
+        let changes = diff_schemas(&from, &to);
+        assert_eq!(
+            changes.statements,
+            [
+                "DROP VIEW names;",
+                "CREATE VIEW names AS SELECT id, name FROM users;",
+            ]
+        );
+    }
+}
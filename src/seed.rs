@@ -0,0 +1,371 @@
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use log::debug;
+use serde::Serialize;
+use sqlx::{AnyConnection, Connection};
+
+use crate::{
+    database::{init, is_marked_production, table_exists},
+    derictive_constants::is_transactional,
+    errors::{MigrenError, Result},
+    features::{MigrateOptions, MigrationsData, execute_statements},
+    history::timestamp,
+    lock::{lock, unlock},
+    schema::Backend,
+    util::checksum,
+};
+
+/// Directory with seed sets inside of migrations directory. Every set is a subdirectory
+pub const SEEDS_DIR_NAME: &str = "seeds";
+
+/// Seed set which can always be applied to production database. Other sets have to be listed
+/// in `production_seed_sets` of `.migren.json`
+pub const BASE_SEED_SET: &str = "base";
+
+/// Seed file which is new or was changed since it was applied
+#[derive(Serialize, Debug, Clone)]
+pub struct SeedToApply {
+    /// File name inside of seed set directory. Database tracks files by set and name
+    pub name: String,
+    pub file: PathBuf,
+    pub checksum: String,
+    /// File was applied before with different content
+    pub changed: bool,
+    #[serde(skip)]
+    pub sql_code: String,
+}
+
+/// Result of applying seed set
+#[derive(Serialize, Debug, Clone)]
+pub struct SeedReport {
+    pub set: String,
+    /// New and changed seed files in order of execution. Planned ones for dry run
+    pub applied: Vec<SeedToApply>,
+    /// Files which were applied already with the same content
+    pub unchanged: Vec<String>,
+    pub dry_run: bool,
+}
+
+/// Checksums of applied seed files of set by file name
+async fn applied_checksums(conn: &mut AnyConnection, set: &str) -> Result<HashMap<String, String>> {
+    if !table_exists(conn, "migren_seeds").await? {
+        return Ok(HashMap::new());
+    }
+
+    let rows = sqlx::query_as::<_, (String, String)>(
+        &Backend::of(conn).sql("SELECT name, checksum FROM migren_seeds WHERE seed_set = $1"),
+    )
+    .bind(set)
+    .fetch_all(conn)
+    .await?;
+    Ok(rows.into_iter().collect())
+}
+
+/// Executes seed file and saves its checksum
+async fn apply_seed(conn: &mut AnyConnection, set: &str, seed: &SeedToApply) -> Result<()> {
+    execute_statements(conn, &seed.file, &seed.sql_code).await?;
+
+    sqlx::query(
+        &Backend::of(conn).sql("DELETE FROM migren_seeds WHERE seed_set = $1 AND name = $2"),
+    )
+    .bind(set)
+    .bind(&seed.name)
+    .execute(&mut *conn)
+    .await?;
+    sqlx::query(&Backend::of(conn).sql(
+        "INSERT INTO migren_seeds (seed_set, name, checksum, applied_at) VALUES ($1, $2, $3, $4)",
+    ))
+    .bind(set)
+    .bind(&seed.name)
+    .bind(&seed.checksum)
+    .bind(timestamp())
+    .execute(&mut *conn)
+    .await?;
+
+    debug!("Applied seed file {:?}", seed.file);
+    Ok(())
+}
+
+/// Applies new and changed files of seed set. Consecutive transactional files
+/// are applied in one transaction. Caller is responsible for locking
+async fn apply_seed_set(
+    conn: &mut AnyConnection,
+    directory: &Path,
+    set: &str,
+    dry_run: bool,
+) -> Result<SeedReport> {
+    let set_dir = directory.join(SEEDS_DIR_NAME).join(set);
+    if !fs::exists(&set_dir)? {
+        return Err(MigrenError::SeedSetNotFound(set_dir));
+    }
+
+    let mut names: Vec<String> = fs::read_dir(&set_dir)?
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_type().is_ok_and(|file_type| file_type.is_file()))
+        .map(|entry| entry.file_name().to_string_lossy().to_string())
+        .filter(|name| name.ends_with(".sql"))
+        .collect();
+    names.sort();
+
+    let applied_checksums = applied_checksums(conn, set).await?;
+    let mut applied = Vec::new();
+    let mut unchanged = Vec::new();
+    for name in names {
+        let file = set_dir.join(&name);
+        let sql_code = fs::read_to_string(&file)?;
+        let checksum = checksum(&sql_code);
+        let changed = match applied_checksums.get(&name) {
+            Some(applied) if *applied == checksum => {
+                unchanged.push(name);
+                continue;
+            }
+            Some(_) => true,
+            None => false,
+        };
+
+        applied.push(SeedToApply {
+            name,
+            file,
+            checksum,
+            changed,
+            sql_code,
+        });
+    }
+
+    if !dry_run {
+        for group in
+            applied.chunk_by(|a, b| is_transactional(&a.sql_code) && is_transactional(&b.sql_code))
+        {
+            if is_transactional(&group[0].sql_code) {
+                let mut tx = conn.begin().await?;
+                for seed in group {
+                    apply_seed(&mut tx, set, seed).await?;
+                }
+                tx.commit().await?;
+            } else {
+                debug!("Applying {:?} outside of transaction", group[0].file);
+                apply_seed(conn, set, &group[0]).await?;
+            }
+        }
+    }
+
+    Ok(SeedReport {
+        set: set.to_string(),
+        applied,
+        unchanged,
+        dry_run,
+    })
+}
+
+/// Applies new and changed files of seed set from `seeds/<set>` in order of file names.
+/// Production database, set by options or marked in database, gets only base set
+/// and sets listed in `production_seed_sets`
+pub async fn seed(
+    conn: &mut AnyConnection,
+    migrations_data: &MigrationsData,
+    set: &str,
+    options: &MigrateOptions,
+) -> Result<SeedReport> {
+    let production = options.production || is_marked_production(conn).await?;
+    let allowed = set == BASE_SEED_SET
+        || migrations_data
+            .production_seed_sets
+            .iter()
+            .any(|allowed| allowed == set);
+    if production && !allowed {
+        return Err(MigrenError::ProductionSeedRefused(set.to_string()));
+    }
+
+    let directory = &migrations_data.directory;
+    if options.dry_run {
+        return apply_seed_set(conn, directory, set, true).await;
+    }
+
+    lock(conn, options.lock_timeout).await?;
+    let result = async {
+        init(conn, options.production).await?;
+        apply_seed_set(conn, directory, set, false).await
+    }
+    .await;
+    let unlocked = unlock(conn).await;
+
+    let report = result?;
+    unlocked?;
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::{test_connection, test_migrations_data};
+
+    fn write_seed(migrations_data: &MigrationsData, set: &str, name: &str, sql_code: &str) {
+        let set_dir = migrations_data.directory.join(SEEDS_DIR_NAME).join(set);
+        fs::create_dir_all(&set_dir).unwrap();
+        fs::write(set_dir.join(name), sql_code).unwrap();
+    }
+
+    async fn connection_with_table() -> AnyConnection {
+        let mut conn = test_connection().await;
+        sqlx::query("CREATE TABLE users (name TEXT)")
+            .execute(&mut conn)
+            .await
+            .unwrap();
+        conn
+    }
+
+    async fn users(conn: &mut AnyConnection) -> Vec<String> {
+        sqlx::query_scalar::<_, String>("SELECT name FROM users ORDER BY name")
+            .fetch_all(conn)
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn applies_only_allowed_sets_to_production() {
+        let mut migrations_data = test_migrations_data("applies_only_allowed_sets_to_production");
+        write_seed(&migrations_data, BASE_SEED_SET, "1_roles.sql", "SELECT 1;");
+        write_seed(&migrations_data, "demo", "1_users.sql", "SELECT 1;");
+        let mut conn = test_connection().await;
+        let options = MigrateOptions {
+            production: true,
+            ..MigrateOptions::default()
+        };
+
+        assert!(matches!(
+            seed(&mut conn, &migrations_data, "demo", &options).await,
+            Err(MigrenError::ProductionSeedRefused(set)) if set == "demo"
+        ));
+        seed(&mut conn, &migrations_data, BASE_SEED_SET, &options)
+            .await
+            .unwrap();
+
+        migrations_data.production_seed_sets = vec!["demo".to_string()];
+        let report = seed(&mut conn, &migrations_data, "demo", &options)
+            .await
+            .unwrap();
+        assert_eq!(report.applied.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn refuses_sets_for_database_marked_production() {
+        let migrations_data = test_migrations_data("refuses_sets_for_database_marked_production");
+        write_seed(&migrations_data, "demo", "1_users.sql", "SELECT 1;");
+        let mut conn = test_connection().await;
+        init(&mut conn, true).await.unwrap();
+
+        assert!(matches!(
+            seed(
+                &mut conn,
+                &migrations_data,
+                "demo",
+                &MigrateOptions::default()
+            )
+            .await,
+            Err(MigrenError::ProductionSeedRefused(_))
+        ));
+        assert!(
+            applied_checksums(&mut conn, "demo")
+                .await
+                .unwrap()
+                .is_empty()
+        );
+    }
+
+    #[tokio::test]
+    async fn reapplies_changed_seeds() {
+        let migrations_data = test_migrations_data("reapplies_changed_seeds");
+        write_seed(
+            &migrations_data,
+            "demo",
+            "1_alice.sql",
+            "INSERT INTO users (name) VALUES ('alice');",
+        );
+        write_seed(
+            &migrations_data,
+            "demo",
+            "2_bob.sql",
+            "INSERT INTO users (name) VALUES ('bob');",
+        );
+        let mut conn = connection_with_table().await;
+        let options = MigrateOptions::default();
+
+        let report = seed(&mut conn, &migrations_data, "demo", &options)
+            .await
+            .unwrap();
+        assert_eq!(report.applied.len(), 2);
+        assert!(report.applied.iter().all(|seed| !seed.changed));
+
+        let report = seed(&mut conn, &migrations_data, "demo", &options)
+            .await
+            .unwrap();
+        assert!(report.applied.is_empty());
+        assert_eq!(report.unchanged, ["1_alice.sql", "2_bob.sql"]);
+
+        write_seed(
+            &migrations_data,
+            "demo",
+            "2_bob.sql",
+            "DELETE FROM users WHERE name = 'bob';\nINSERT INTO users (name) VALUES ('bobby');",
+        );
+        let report = seed(&mut conn, &migrations_data, "demo", &options)
+            .await
+            .unwrap();
+        assert_eq!(report.applied.len(), 1);
+        assert_eq!(report.applied[0].name, "2_bob.sql");
+        assert!(report.applied[0].changed);
+        assert_eq!(report.unchanged, ["1_alice.sql"]);
+        assert_eq!(users(&mut conn).await, ["alice", "bobby"]);
+    }
+
+    #[tokio::test]
+    async fn rolls_back_transactional_seeds_together() {
+        let migrations_data = test_migrations_data("rolls_back_transactional_seeds_together");
+        write_seed(
+            &migrations_data,
+            "demo",
+            "1_alice.sql",
+            "INSERT INTO users (name) VALUES ('alice');",
+        );
+        write_seed(
+            &migrations_data,
+            "demo",
+            "2_bob.sql",
+            "-- migren:no-transaction\nINSERT INTO users (name) VALUES ('bob');",
+        );
+        write_seed(
+            &migrations_data,
+            "demo",
+            "3_carol.sql",
+            "INSERT INTO users (name) VALUES ('carol');",
+        );
+        write_seed(
+            &migrations_data,
+            "demo",
+            "4_broken.sql",
+            "SELECT * FROM missing;",
+        );
+        let mut conn = connection_with_table().await;
+
+        assert!(
+            seed(
+                &mut conn,
+                &migrations_data,
+                "demo",
+                &MigrateOptions::default()
+            )
+            .await
+            .is_err()
+        );
+
+        assert_eq!(users(&mut conn).await, ["alice", "bob"]);
+        let applied = applied_checksums(&mut conn, "demo").await.unwrap();
+        let mut names: Vec<&String> = applied.keys().collect();
+        names.sort();
+        assert_eq!(names, ["1_alice.sql", "2_bob.sql"]);
+    }
+}
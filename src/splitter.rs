@@ -0,0 +1,472 @@
+use log::debug;
+
+use crate::{derictive_constants::SqlDirective, schema::Backend};
+
+/// Single statement of sql file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Statement {
+    /// Statement code without delimiter
+    pub sql: String,
+    /// Line of sql file statement starts at. Starts from 1
+    pub line: usize,
+}
+
+const DEFAULT_DELIMITER: &str = ";";
+
+/// How many first words of statement are kept to recognise trigger or routine
+const HEAD_WORDS: usize = 8;
+
+/// Splits sql code into statements.
+///
+/// Delimiters inside of quoted strings and identifiers (`'...'`, `"..."`, `` `...` ``),
+/// Postgres dollar-quoted strings (`$tag$...$tag$`) and comments (`-- ...`, `/* ... */`)
+/// are ignored. MySQL `DELIMITER` lines change delimiter for the following statements.
+/// `-- migren:split` directive ends statement as well.
+///
+/// `BEGIN ... END` bodies of triggers (and of MySQL routines and events) and Postgres
+/// `BEGIN ATOMIC ... END` bodies are kept whole, with `CASE ... END` and MySQL
+/// `IF`, `LOOP`, `WHILE` and `REPEAT` blocks inside of them.
+/// Statements which contain only comments are skipped.
+///
+/// Backslash escapes strings on MySQL and in Postgres `E'...'` strings,
+/// `# ...` is a comment on MySQL.
+pub fn split_sql(sql_code: &str, backend: Backend) -> Vec<Statement> {
+    Splitter::new(sql_code, backend).split()
+}
+
+struct Splitter<'a> {
+    code: &'a str,
+    backend: Backend,
+    pos: usize,
+    line: usize,
+    delimiter: String,
+    statements: Vec<Statement>,
+    buffer: String,
+    /// Line where first code of current statement was found
+    statement_line: Option<usize>,
+    /// First words of current statement, uppercased
+    head: Vec<String>,
+    /// Previous word of current statement, uppercased
+    last_word: String,
+    /// Depth of `BEGIN ... END` and nested blocks. Delimiter inside of them does not end statement
+    depth: usize,
+}
+
+impl<'a> Splitter<'a> {
+    fn new(code: &'a str, backend: Backend) -> Self {
+        Self {
+            code,
+            backend,
+            pos: 0,
+            line: 1,
+            delimiter: DEFAULT_DELIMITER.to_string(),
+            statements: Vec::new(),
+            buffer: String::new(),
+            statement_line: None,
+            head: Vec::new(),
+            last_word: String::new(),
+            depth: 0,
+        }
+    }
+
+    fn rest(&self) -> &'a str {
+        &self.code[self.pos..]
+    }
+
+    fn at_line_start(&self) -> bool {
+        self.code[..self.pos]
+            .rsplit('\n')
+            .next()
+            .is_none_or(|line| line.trim().is_empty())
+    }
+
+    /// Moves `len` bytes from code into statement buffer
+    fn take(&mut self, len: usize) {
+        let chunk = &self.code[self.pos..self.pos + len];
+        self.line += chunk.matches('\n').count();
+        self.buffer.push_str(chunk);
+        self.pos += len;
+    }
+
+    /// Moves `len` bytes of code into statement buffer and marks statement as having code
+    fn take_code(&mut self, len: usize) {
+        if self.statement_line.is_none() {
+            self.statement_line = Some(self.line);
+        }
+        self.take(len);
+    }
+
+    /// Skips `len` bytes of code without adding them to statement
+    fn skip(&mut self, len: usize) {
+        self.line += self.code[self.pos..self.pos + len].matches('\n').count();
+        self.pos += len;
+    }
+
+    fn flush(&mut self) {
+        if let Some(line) = self.statement_line.take() {
+            self.statements.push(Statement {
+                sql: self.buffer.trim().to_string(),
+                line,
+            });
+        }
+        self.buffer = String::new();
+        self.head.clear();
+        self.last_word.clear();
+        self.depth = 0;
+    }
+
+    /// Word after current position, skipping whitespace
+    fn next_word(&self) -> &'a str {
+        let rest = self.rest().trim_start();
+        let len = rest
+            .find(|c: char| !(c.is_alphanumeric() || c == '_'))
+            .unwrap_or(rest.len());
+        &rest[..len]
+    }
+
+    /// True if statement creates object whose body is `BEGIN ... END` block
+    fn has_body(&self) -> bool {
+        let kinds: &[&str] = match self.backend {
+            Backend::MySql => &["TRIGGER", "PROCEDURE", "FUNCTION", "EVENT"],
+            _ => &["TRIGGER"],
+        };
+        self.head.first().is_some_and(|word| word == "CREATE")
+            && self.head.iter().any(|word| kinds.contains(&word.as_str()))
+    }
+
+    /// Counts blocks opened and closed by `word`, which was just taken
+    fn track_blocks(&mut self, word: &str) {
+        let word = word.to_ascii_uppercase();
+        if self.head.len() < HEAD_WORDS {
+            self.head.push(word.clone());
+        }
+
+        let after_end = self.last_word == "END";
+        let opens = match word.as_str() {
+            "BEGIN" => match self.backend {
+                Backend::Postgres => {
+                    self.head.first().is_some_and(|word| word == "CREATE")
+                        && self.next_word().eq_ignore_ascii_case("ATOMIC")
+                }
+                _ => self.depth > 0 || self.has_body(),
+            },
+            "CASE" => self.depth > 0 && !after_end,
+            // `IF(...)` function and `IF EXISTS` are not blocks
+            "IF" | "LOOP" | "WHILE" | "REPEAT" => {
+                self.backend == Backend::MySql
+                    && self.depth > 0
+                    && !after_end
+                    && !self.rest().trim_start().starts_with('(')
+                    && !self.next_word().eq_ignore_ascii_case("EXISTS")
+            }
+            _ => false,
+        };
+        if opens {
+            self.depth += 1;
+        } else if word == "END" && self.depth > 0 {
+            self.depth -= 1;
+        }
+
+        self.last_word = word;
+    }
+
+    fn split(mut self) -> Vec<Statement> {
+        while !self.rest().is_empty() {
+            let rest = self.rest();
+
+            if self.statement_line.is_none()
+                && self.at_line_start()
+                && rest
+                    .get(..10)
+                    .is_some_and(|word| word.eq_ignore_ascii_case("delimiter "))
+            {
+                let line_len = rest.find('\n').unwrap_or(rest.len());
+                let delimiter = rest[10..line_len].trim();
+                if !delimiter.is_empty() {
+                    debug!("Delimiter changed to {delimiter}");
+                    self.delimiter = delimiter.to_string();
+                }
+                self.skip(line_len);
+            } else if rest.starts_with(&self.delimiter)
+                && (self.depth == 0 || self.delimiter != DEFAULT_DELIMITER)
+            {
+                self.skip(self.delimiter.len());
+                self.flush();
+            } else if rest.starts_with("--")
+                || (self.backend == Backend::MySql && rest.starts_with('#'))
+            {
+                let len = rest.find('\n').unwrap_or(rest.len());
+                let comment = &rest[..len];
+                if let Some(SqlDirective::Split) = SqlDirective::match_str(comment) {
+                    self.flush();
+                }
+                self.take(len);
+            } else if let Some(comment) = rest.strip_prefix("/*") {
+                let len = comment.find("*/").map(|end| end + 4).unwrap_or(rest.len());
+                self.take(len);
+            } else if rest.starts_with('\'') || rest.starts_with('"') || rest.starts_with('`') {
+                let escapes = match self.backend {
+                    Backend::MySql => !rest.starts_with('`'),
+                    Backend::Postgres => {
+                        rest.starts_with('\'') && self.buffer.ends_with(['E', 'e'])
+                    }
+                    Backend::Sqlite => false,
+                };
+                let len = quoted_len(rest, escapes);
+                self.take_code(len);
+            } else if let Some(tag) = dollar_quote_tag(rest).filter(|_| {
+                !self
+                    .buffer
+                    .ends_with(|c: char| c.is_alphanumeric() || c == '_')
+            }) {
+                let len = rest[tag.len()..]
+                    .find(tag)
+                    .map(|end| end + tag.len() * 2)
+                    .unwrap_or(rest.len());
+                self.take_code(len);
+            } else if rest.starts_with(|c: char| c.is_alphabetic() || c == '_')
+                && !self
+                    .buffer
+                    .ends_with(|c: char| c.is_alphanumeric() || c == '_' || c == '$' || c == '.')
+            {
+                let len = rest
+                    .find(|c: char| !(c.is_alphanumeric() || c == '_' || c == '$'))
+                    .unwrap_or(rest.len());
+                self.take_code(len);
+                self.track_blocks(&rest[..len]);
+            } else {
+                let len = rest.chars().next().unwrap().len_utf8();
+                if rest.starts_with(char::is_whitespace) {
+                    self.take(len);
+                } else {
+                    self.take_code(len);
+                }
+            }
+        }
+
+        self.flush();
+        self.statements
+    }
+}
+
+/// Length of quoted string or identifier at the start of `code`, including quotes.
+/// Doubled quotes (`'it''s'`) are read as two adjacent strings, which is the same for splitting
+fn quoted_len(code: &str, backslash_escapes: bool) -> usize {
+    let quote = code.chars().next().unwrap();
+    let mut chars = code.char_indices().skip(1);
+
+    while let Some((index, char)) = chars.next() {
+        if backslash_escapes && char == '\\' {
+            chars.next();
+        } else if char == quote {
+            return index + 1;
+        }
+    }
+
+    code.len()
+}
+
+/// Returns `$tag$` if code starts with Postgres dollar quote
+fn dollar_quote_tag(code: &str) -> Option<&str> {
+    let tag_code = code.strip_prefix('$')?;
+    let tag_len = tag_code.find(|c: char| !(c.is_alphanumeric() || c == '_'))?;
+
+    if !tag_code[tag_len..].starts_with('$') || tag_code.starts_with(|c: char| c.is_ascii_digit()) {
+        return None;
+    }
+
+    Some(&code[..tag_len + 2])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sqls(sql_code: &str, backend: Backend) -> Vec<String> {
+        split_sql(sql_code, backend)
+            .into_iter()
+            .map(|statement| statement.sql)
+            .collect()
+    }
+
+    #[test]
+    fn splits_by_semicolon() {
+        assert_eq!(
+            sqls("CREATE TABLE a (id INT);\nDROP TABLE b;", Backend::Postgres),
+            ["CREATE TABLE a (id INT)", "DROP TABLE b"]
+        );
+        assert_eq!(sqls("SELECT 1", Backend::Sqlite), ["SELECT 1"]);
+    }
+
+    #[test]
+    fn ignores_delimiters_in_quotes() {
+        let sql_code = "INSERT INTO t VALUES ('a;b', \"c;d\", `e;f`);\nSELECT 1;";
+        for backend in [Backend::Postgres, Backend::MySql, Backend::Sqlite] {
+            assert_eq!(
+                sqls(sql_code, backend),
+                ["INSERT INTO t VALUES ('a;b', \"c;d\", `e;f`)", "SELECT 1"]
+            );
+        }
+    }
+
+    #[test]
+    fn reads_doubled_quotes() {
+        assert_eq!(
+            sqls(
+                "INSERT INTO t VALUES ('it''s; ok');SELECT 1;",
+                Backend::Sqlite
+            ),
+            ["INSERT INTO t VALUES ('it''s; ok')", "SELECT 1"]
+        );
+    }
+
+    #[test]
+    fn reads_backslash_escapes_on_mysql() {
+        let sql_code = "INSERT INTO t VALUES ('it\\'s; ok', \"say \\\"hi;\\\"\");\nSELECT 1;";
+        assert_eq!(
+            sqls(sql_code, Backend::MySql),
+            [
+                "INSERT INTO t VALUES ('it\\'s; ok', \"say \\\"hi;\\\"\")",
+                "SELECT 1"
+            ]
+        );
+    }
+
+    #[test]
+    fn reads_backslash_escapes_in_postgres_e_strings_only() {
+        assert_eq!(
+            sqls("SELECT E'it\\'s; ok';SELECT 1;", Backend::Postgres),
+            ["SELECT E'it\\'s; ok'", "SELECT 1"]
+        );
+        // Backslash does not escape in standard strings
+        assert_eq!(
+            sqls("SELECT 'C:\\';SELECT 1;", Backend::Postgres),
+            ["SELECT 'C:\\'", "SELECT 1"]
+        );
+    }
+
+    #[test]
+    fn ignores_delimiters_in_dollar_quotes() {
+        let sql_code = "CREATE FUNCTION f() RETURNS INT AS $body$ BEGIN RETURN 1; END; $body$ LANGUAGE plpgsql;\nDO $$ BEGIN PERFORM 1; END $$;";
+        assert_eq!(
+            sqls(sql_code, Backend::Postgres),
+            [
+                "CREATE FUNCTION f() RETURNS INT AS $body$ BEGIN RETURN 1; END; $body$ LANGUAGE plpgsql",
+                "DO $$ BEGIN PERFORM 1; END $$"
+            ]
+        );
+    }
+
+    #[test]
+    fn ignores_delimiters_in_comments() {
+        let sql_code = "-- it's; comment\nSELECT 1; /* multi;\nline */ SELECT 2;";
+        assert_eq!(
+            sqls(sql_code, Backend::Postgres),
+            ["-- it's; comment\nSELECT 1", "/* multi;\nline */ SELECT 2"]
+        );
+    }
+
+    #[test]
+    fn reads_hash_comments_on_mysql() {
+        assert_eq!(
+            sqls("# it's; comment\nSELECT 1;", Backend::MySql),
+            ["# it's; comment\nSELECT 1"]
+        );
+    }
+
+    #[test]
+    fn skips_statements_with_only_comments() {
+        assert_eq!(
+            sqls("-- 1 - first up query\n\n/* nothing */\n", Backend::Sqlite),
+            Vec::<String>::new()
+        );
+        assert_eq!(
+            sqls("SELECT 1;\n-- trailing comment\n", Backend::Sqlite),
+            ["SELECT 1"]
+        );
+    }
+
+    #[test]
+    fn changes_delimiter() {
+        let sql_code = "DELIMITER //\nCREATE TRIGGER trg BEFORE INSERT ON t FOR EACH ROW BEGIN SET NEW.a = 1; END//\nDELIMITER ;\nSELECT 1;";
+        assert_eq!(
+            sqls(sql_code, Backend::MySql),
+            [
+                "CREATE TRIGGER trg BEFORE INSERT ON t FOR EACH ROW BEGIN SET NEW.a = 1; END",
+                "SELECT 1"
+            ]
+        );
+    }
+
+    #[test]
+    fn splits_by_split_directive() {
+        let sql_code = "SELECT 1\n-- migren:split\nSELECT 2";
+        assert_eq!(
+            sqls(sql_code, Backend::Sqlite),
+            ["SELECT 1", "-- migren:split\nSELECT 2"]
+        );
+    }
+
+    #[test]
+    fn reports_line_of_first_code() {
+        let sql_code = "-- header\n\nCREATE TABLE a (\n    id INT\n);\n\n-- comment\nINSERT INTO a VALUES ('multi\nline');\nSELECT 1; SELECT 2;";
+        let lines: Vec<usize> = split_sql(sql_code, Backend::Postgres)
+            .into_iter()
+            .map(|statement| statement.line)
+            .collect();
+        assert_eq!(lines, [3, 8, 10, 10]);
+    }
+
+    #[test]
+    fn keeps_trigger_bodies_whole() {
+        let sql_code =
+            "CREATE TRIGGER t AFTER INSERT ON a BEGIN INSERT INTO a VALUES (2); END;\nSELECT 1;";
+        for backend in [Backend::Sqlite, Backend::MySql] {
+            assert_eq!(
+                sqls(sql_code, backend),
+                [
+                    "CREATE TRIGGER t AFTER INSERT ON a BEGIN INSERT INTO a VALUES (2); END",
+                    "SELECT 1"
+                ]
+            );
+        }
+    }
+
+    #[test]
+    fn keeps_case_inside_of_trigger_bodies() {
+        let sql_code = "CREATE TEMP TRIGGER IF NOT EXISTS t BEFORE UPDATE ON a\nBEGIN\n  UPDATE a SET kind = CASE WHEN NEW.id > 0 THEN 'end' ELSE 'begin' END WHERE id = NEW.id;\n  SELECT a.end FROM a;\nEND;\nDROP TABLE b;";
+        assert_eq!(
+            sqls(sql_code, Backend::Sqlite),
+            [
+                "CREATE TEMP TRIGGER IF NOT EXISTS t BEFORE UPDATE ON a\nBEGIN\n  UPDATE a SET kind = CASE WHEN NEW.id > 0 THEN 'end' ELSE 'begin' END WHERE id = NEW.id;\n  SELECT a.end FROM a;\nEND",
+                "DROP TABLE b"
+            ]
+        );
+    }
+
+    #[test]
+    fn keeps_mysql_routine_blocks_whole() {
+        let sql_code = "CREATE PROCEDURE p() BEGIN IF IF(1, 1, 0) THEN SELECT 1; END IF; CASE WHEN 1 THEN SELECT 2; END CASE; END;\nSELECT 3;";
+        assert_eq!(
+            sqls(sql_code, Backend::MySql),
+            [
+                "CREATE PROCEDURE p() BEGIN IF IF(1, 1, 0) THEN SELECT 1; END IF; CASE WHEN 1 THEN SELECT 2; END CASE; END",
+                "SELECT 3"
+            ]
+        );
+    }
+
+    #[test]
+    fn keeps_postgres_atomic_bodies_whole() {
+        let sql_code = "CREATE FUNCTION f(x INT) RETURNS TEXT LANGUAGE sql\nBEGIN ATOMIC\n  SELECT CASE WHEN x > 0 THEN 'a' END;\nEND;\nBEGIN;\nSELECT 1;\nEND;";
+        assert_eq!(
+            sqls(sql_code, Backend::Postgres),
+            [
+                "CREATE FUNCTION f(x INT) RETURNS TEXT LANGUAGE sql\nBEGIN ATOMIC\n  SELECT CASE WHEN x > 0 THEN 'a' END;\nEND",
+                "BEGIN",
+                "SELECT 1",
+                "END"
+            ]
+        );
+    }
+}
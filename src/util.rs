@@ -1,6 +1,11 @@
-use std::{fs, path::PathBuf};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
 
-use log::info;
+use log::{debug, info};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
 
 use crate::{
     errors::{MigrenError, Result},
@@ -14,7 +19,7 @@ pub fn default_migrations_dir() -> PathBuf {
     std::env::current_dir().unwrap()
 }
 
-pub fn create_dir_if_not_exists(path: &PathBuf) -> Result<()> {
+pub fn create_dir_if_not_exists(path: &Path) -> Result<()> {
     if !std::fs::exists(path)? {
         log::info!(target: "create_dir_if_not_exists", "Directory {:?} does not exists. Creating it", path);
         std::fs::create_dir(path)?;
@@ -23,55 +28,158 @@ pub fn create_dir_if_not_exists(path: &PathBuf) -> Result<()> {
     Ok(())
 }
 
-/// Loads migration data from file
-pub fn load_migrations_data(migrations_file: &PathBuf) -> Result<MigrationsData> {
-    if !fs::exists(migrations_file)? {
+/// Loads migration data from `.migren.json` file inside of migrations directory
+pub fn load_migrations_data(directory: &Path) -> Result<MigrationsData> {
+    let migrations_file = directory.join(MIGRATIONS_FILE_NAME);
+
+    if !fs::exists(&migrations_file)? {
         info!(target: "load_migrations_data", "File {:?} does not exist. Creating one", migrations_file);
 
         let migrations_data = MigrationsData::default();
         let json_str = serde_json::to_string(&migrations_data)?;
 
-        fs::write(migrations_file, &json_str)?;
+        fs::write(&migrations_file, &json_str)?;
     }
 
-    let migrations_data: MigrationsData =
-        serde_json::from_str(&fs::read_to_string(migrations_file)?)?;
+    let mut migrations_data: MigrationsData =
+        serde_json::from_str(&fs::read_to_string(&migrations_file)?)?;
+    migrations_data.directory = directory.to_path_buf();
     Ok(migrations_data)
 }
 
-/// Create files for migration
-pub fn create_migration_files(migration_id: u32, migration_name: &str) -> Result<MigrationFiles> {
-    info!("Creating migration files for {migration_name}.");
+/// Saves migration data into `.migren.json` file inside of its migrations directory
+pub fn save_migrations_data(migrations_data: &MigrationsData) -> Result<PathBuf> {
+    let migrations_file = migrations_data.directory.join(MIGRATIONS_FILE_NAME);
+    let json_str = serde_json::to_string(migrations_data)?;
+
+    fs::write(&migrations_file, json_str)?;
+    Ok(migrations_file)
+}
+
+/// Create files for migration inside of migrations directory.
+/// Returned file names are relative to this directory.
+pub fn create_migration_files(
+    directory: &Path,
+    migration_id: u64,
+    migration_name: &str,
+) -> Result<MigrationFiles> {
+    debug!("Creating migration files for {migration_name}.");
     let up_migration_file = PathBuf::from(format!("{migration_id}_{migration_name}_up.sql"));
 
     let down_migration_file = PathBuf::from(format!("{migration_id}_{migration_name}_down.sql"));
 
     fs::write(
-        &up_migration_file,
+        directory.join(&up_migration_file),
         format!("-- {migration_id} - {migration_name} up query"),
     )?;
-    info!("Wrote {up_migration_file:?}");
+    debug!("Wrote {up_migration_file:?}");
 
     fs::write(
-        &down_migration_file,
+        directory.join(&down_migration_file),
         format!("-- {migration_id} - {migration_name} down query"),
     )?;
-    info!("Wrote {down_migration_file:?}");
+    debug!("Wrote {down_migration_file:?}");
 
     Ok(MigrationFiles {
-        up_migration_file: PathBuf::from(up_migration_file),
-        down_migration_file: PathBuf::from(down_migration_file),
+        up_migration_file,
+        down_migration_file,
     })
 }
 
-pub fn assert_migration_files_exists(migration_data: &MigrationData) -> Result<()> {
-    if !fs::exists(&migration_data.files.up_migration_file)?
-        || !fs::exists(&migration_data.files.down_migration_file)?
+pub fn assert_migration_files_exists(
+    directory: &Path,
+    migration_data: &MigrationData,
+) -> Result<()> {
+    if !fs::exists(directory.join(&migration_data.files.up_migration_file))?
+        || !fs::exists(directory.join(&migration_data.files.down_migration_file))?
     {
-        return Err(MigrenError::MigrationFilesDoesNotExsists(
+        return Err(MigrenError::MigrationFilesDoesNotExsists(Box::new(
             migration_data.clone(),
-        ));
+        )));
     }
 
     Ok(())
 }
+
+/// Returns sha256 checksum of content as hex string
+pub fn checksum(content: impl AsRef<[u8]>) -> String {
+    hex::encode(Sha256::digest(content))
+}
+
+/// Returns sha256 checksum of file content as hex string
+pub fn file_checksum(path: &Path) -> Result<String> {
+    Ok(checksum(fs::read(path)?))
+}
+
+/// Line of [`diff_lines`] result
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum DiffLine {
+    Same(String),
+    Removed(String),
+    Added(String),
+}
+
+/// Line-by-line diff of two texts
+pub fn diff_lines(old: &str, new: &str) -> Vec<DiffLine> {
+    let old: Vec<&str> = old.lines().collect();
+    let new: Vec<&str> = new.lines().collect();
+
+    // Longest common subsequence lengths of old[i..] and new[j..]
+    let mut lcs = vec![vec![0usize; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            lcs[i][j] = if old[i] == new[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+
+    let (mut i, mut j) = (0, 0);
+    let mut diff = Vec::with_capacity(old.len().max(new.len()));
+    while i < old.len() || j < new.len() {
+        if i < old.len() && j < new.len() && old[i] == new[j] {
+            diff.push(DiffLine::Same(old[i].to_string()));
+            i += 1;
+            j += 1;
+        } else if j < new.len() && (i == old.len() || lcs[i][j + 1] >= lcs[i + 1][j]) {
+            diff.push(DiffLine::Added(new[j].to_string()));
+            j += 1;
+        } else {
+            diff.push(DiffLine::Removed(old[i].to_string()));
+            i += 1;
+        }
+    }
+
+    diff
+}
+
+/// Empty temporary directory for test. Removes files left by previous run of the same test
+#[cfg(test)]
+pub(crate) fn test_directory(test_name: &str) -> PathBuf {
+    let directory = std::env::temp_dir().join(format!("migren-test-{test_name}"));
+    if fs::exists(&directory).unwrap() {
+        fs::remove_dir_all(&directory).unwrap();
+    }
+    fs::create_dir_all(&directory).unwrap();
+    directory
+}
+
+/// Migrations data in empty temporary directory for test, with initial migration only
+#[cfg(test)]
+pub(crate) fn test_migrations_data(test_name: &str) -> MigrationsData {
+    MigrationsData {
+        directory: test_directory(test_name),
+        ..MigrationsData::default()
+    }
+}
+
+/// In-memory SQLite database with migren tables for test
+#[cfg(test)]
+pub(crate) async fn test_connection() -> sqlx::AnyConnection {
+    let mut conn = crate::database::connect("sqlite::memory:").await.unwrap();
+    crate::database::init(&mut conn, false).await.unwrap();
+    conn
+}
@@ -0,0 +1,409 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fmt, fs,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    errors::Result,
+    features::{IdFormat, MigrationsData},
+    schema::SCHEMA_FILE_NAME,
+};
+
+/// Problem of `.migren.json` or migrations directory found by [`verify_migrations_data`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum VerifyIssue {
+    /// Migrations directory has no `.migren.json`
+    MissingMigrationsFile(PathBuf),
+    /// `.migren.json` has no 0 migration every other migration starts from
+    MissingInitialMigration,
+    /// Several migrations have the same id
+    DuplicateId(u64),
+    /// Migration links to migration which does not exist
+    MissingLink { id: u64, linked_id: u64 },
+    /// `id` has `next_id` as next migration, but `next_id` does not have `id` as previous one
+    AsymmetricNext { id: u64, next_id: u64 },
+    /// `id` has `prev_id` as previous migration, but `prev_id` does not have `id` as next one
+    AsymmetricPrev { id: u64, prev_id: u64 },
+    /// Migrations which depend on each other in circle
+    Cycle(Vec<u64>),
+    /// Migration can not be reached from 0 migration or `migrations_start_id`
+    Unreachable(u64),
+    /// `migrations_start_id` points to migration which does not exist or has previous migrations
+    InvalidStartId(u64),
+    /// `migrations_counter` points to migration which does not exist
+    CounterNotFound(u64),
+    /// Counter ids of new migrations would collide with existing migration
+    CounterBehind { counter: u64, max_id: u64 },
+    /// Migration file referenced in `.migren.json` does not exist
+    MissingFile { id: u64, file: PathBuf },
+    /// `.sql` file in migrations directory which no migration references
+    StrayFile(PathBuf),
+}
+
+impl fmt::Display for VerifyIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VerifyIssue::MissingMigrationsFile(file) => write!(f, "File {file:?} does not exist"),
+            VerifyIssue::MissingInitialMigration => write!(f, "Initial migration 0 is missing"),
+            VerifyIssue::DuplicateId(id) => write!(f, "Several migrations have id {id}"),
+            VerifyIssue::MissingLink { id, linked_id } => write!(
+                f,
+                "Migration {id} links to migration {linked_id}, which does not exist"
+            ),
+            VerifyIssue::AsymmetricNext { id, next_id } => write!(
+                f,
+                "Migration {id} has next migration {next_id}, but {next_id} does not have {id} as previous"
+            ),
+            VerifyIssue::AsymmetricPrev { id, prev_id } => write!(
+                f,
+                "Migration {id} has previous migration {prev_id}, but {prev_id} does not have {id} as next"
+            ),
+            VerifyIssue::Cycle(ids) => write!(f, "Circular migrations found between {ids:?}"),
+            VerifyIssue::Unreachable(id) => {
+                write!(
+                    f,
+                    "Migration {id} can not be reached from initial migration"
+                )
+            }
+            VerifyIssue::InvalidStartId(id) => write!(
+                f,
+                "migrations_start_id {id} does not point to migration without previous migrations"
+            ),
+            VerifyIssue::CounterNotFound(counter) => write!(
+                f,
+                "migrations_counter {counter} does not point to existing migration"
+            ),
+            VerifyIssue::CounterBehind { counter, max_id } => write!(
+                f,
+                "migrations_counter {counter} is less than the biggest migration id {max_id}"
+            ),
+            VerifyIssue::MissingFile { id, file } => {
+                write!(f, "File {file:?} of migration {id} does not exist")
+            }
+            VerifyIssue::StrayFile(file) => {
+                write!(f, "File {file:?} is not referenced by any migration")
+            }
+        }
+    }
+}
+
+/// Checks `.migren.json` and migrations directory without connecting to database
+pub fn verify_migrations_data(migrations_data: &MigrationsData) -> Result<Vec<VerifyIssue>> {
+    let mut issues = Vec::new();
+
+    let mut ids = HashSet::new();
+    for migration in &migrations_data.migrations {
+        if !ids.insert(migration.id) {
+            issues.push(VerifyIssue::DuplicateId(migration.id));
+        }
+    }
+    if !ids.contains(&0) {
+        issues.push(VerifyIssue::MissingInitialMigration);
+    }
+
+    for migration in &migrations_data.migrations {
+        for next_id in &migration.next_migration_ids {
+            match migrations_data.migration_by_id(*next_id) {
+                None => issues.push(VerifyIssue::MissingLink {
+                    id: migration.id,
+                    linked_id: *next_id,
+                }),
+                Some(next) if !next.prev_migration_ids.contains(&migration.id) => {
+                    issues.push(VerifyIssue::AsymmetricNext {
+                        id: migration.id,
+                        next_id: *next_id,
+                    })
+                }
+                Some(_) => {}
+            }
+        }
+        for prev_id in &migration.prev_migration_ids {
+            match migrations_data.migration_by_id(*prev_id) {
+                None => issues.push(VerifyIssue::MissingLink {
+                    id: migration.id,
+                    linked_id: *prev_id,
+                }),
+                Some(prev) if !prev.next_migration_ids.contains(&migration.id) => {
+                    issues.push(VerifyIssue::AsymmetricPrev {
+                        id: migration.id,
+                        prev_id: *prev_id,
+                    })
+                }
+                Some(_) => {}
+            }
+        }
+    }
+
+    let circular = circular_migrations(migrations_data);
+    if !circular.is_empty() {
+        issues.push(VerifyIssue::Cycle(circular));
+    }
+
+    if let Some(start_id) = migrations_data.migrations_start_id
+        && !migrations_data
+            .migration_by_id(start_id)
+            .is_some_and(|migration| migration.prev_migration_ids.is_empty())
+    {
+        issues.push(VerifyIssue::InvalidStartId(start_id));
+    }
+
+    let roots = [Some(0), migrations_data.migrations_start_id];
+    let reachable = reachable_migrations(migrations_data, roots.into_iter().flatten());
+    let mut unreachable: Vec<u64> = ids.difference(&reachable).copied().collect();
+    unreachable.sort();
+    issues.extend(unreachable.into_iter().map(VerifyIssue::Unreachable));
+
+    let counter = migrations_data.migrations_counter;
+    if !ids.contains(&counter) {
+        issues.push(VerifyIssue::CounterNotFound(counter));
+    }
+    let max_id = ids.iter().copied().max().unwrap_or(0);
+    if migrations_data.id_format == IdFormat::Counter && counter < max_id {
+        issues.push(VerifyIssue::CounterBehind { counter, max_id });
+    }
+
+    let mut referenced = HashSet::new();
+    // Skipping 0 migration, it has no files
+    for migration in migrations_data.migrations.iter().filter(|m| m.id != 0) {
+        for file in [
+            &migration.files.up_migration_file,
+            &migration.files.down_migration_file,
+        ] {
+            if !fs::exists(migrations_data.directory.join(file))? {
+                issues.push(VerifyIssue::MissingFile {
+                    id: migration.id,
+                    file: file.clone(),
+                });
+            }
+            referenced.insert(file.clone());
+        }
+    }
+
+    let mut stray: Vec<PathBuf> = fs::read_dir(&migrations_data.directory)?
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_type().is_ok_and(|file_type| file_type.is_file()))
+        .map(|entry| PathBuf::from(entry.file_name()))
+        .filter(|file| file.extension().is_some_and(|extension| extension == "sql"))
+        .filter(|file| !referenced.contains(file) && file != Path::new(SCHEMA_FILE_NAME))
+        .collect();
+    stray.sort();
+    issues.extend(stray.into_iter().map(VerifyIssue::StrayFile));
+
+    Ok(issues)
+}
+
+/// Migrations which can not be ordered because they are previous to each other in circle
+fn circular_migrations(migrations_data: &MigrationsData) -> Vec<u64> {
+    let mut remaining_prev: HashMap<u64, usize> = migrations_data
+        .migrations
+        .iter()
+        .map(|migration| {
+            // Missing previous migrations are reported as missing links
+            let prev_count = migration
+                .prev_migration_ids
+                .iter()
+                .filter(|prev_id| migrations_data.migration_by_id(**prev_id).is_some())
+                .count();
+            (migration.id, prev_count)
+        })
+        .collect();
+    let mut ready: Vec<u64> = remaining_prev
+        .iter()
+        .filter(|(_, count)| **count == 0)
+        .map(|(id, _)| *id)
+        .collect();
+
+    while let Some(id) = ready.pop() {
+        remaining_prev.remove(&id);
+        for migration in &migrations_data.migrations {
+            let links = migration
+                .prev_migration_ids
+                .iter()
+                .filter(|prev_id| **prev_id == id)
+                .count();
+            if let Some(count) = remaining_prev.get_mut(&migration.id).filter(|_| links > 0) {
+                *count -= links;
+                if *count == 0 {
+                    ready.push(migration.id);
+                }
+            }
+        }
+    }
+
+    let mut circular: Vec<u64> = remaining_prev.into_keys().collect();
+    circular.sort();
+    circular
+}
+
+/// Migrations which can be reached from `roots` following next migrations
+fn reachable_migrations(
+    migrations_data: &MigrationsData,
+    roots: impl IntoIterator<Item = u64>,
+) -> HashSet<u64> {
+    let mut reachable = HashSet::new();
+    let mut to_visit: Vec<u64> = roots.into_iter().collect();
+
+    while let Some(id) = to_visit.pop() {
+        if !reachable.insert(id) {
+            continue;
+        }
+        if let Some(migration) = migrations_data.migration_by_id(id) {
+            to_visit.extend(&migration.next_migration_ids);
+        }
+    }
+
+    reachable
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::test_migrations_data;
+
+    /// 0 -> 1 -> 2 with files in temporary directory
+    fn migrations_data(test_name: &str) -> MigrationsData {
+        let mut migrations_data = test_migrations_data(test_name);
+        migrations_data.new_migration("a").unwrap();
+        migrations_data.new_migration("b").unwrap();
+        migrations_data
+    }
+
+    #[test]
+    fn accepts_valid_migrations() {
+        let mut migrations_data = migrations_data("accepts_valid_migrations");
+        fs::write(migrations_data.directory.join(SCHEMA_FILE_NAME), "").unwrap();
+        assert_eq!(verify_migrations_data(&migrations_data).unwrap(), []);
+
+        migrations_data.new_migration("c").unwrap();
+        let mut diverged = migrations_data.migration_by_id(3).unwrap().clone();
+        diverged.id = 4;
+        diverged.prev_migration_ids = vec![2];
+        migrations_data.migrations.push(diverged);
+        migrations_data
+            .migration_by_id_mut(2)
+            .unwrap()
+            .next_migration_ids
+            .push(4);
+        migrations_data.migrations_counter = 4;
+        assert_eq!(verify_migrations_data(&migrations_data).unwrap(), []);
+    }
+
+    #[test]
+    fn reports_broken_links() {
+        let mut migrations_data = migrations_data("reports_broken_links");
+        migrations_data
+            .migration_by_id_mut(1)
+            .unwrap()
+            .next_migration_ids = vec![2, 7];
+        migrations_data
+            .migration_by_id_mut(2)
+            .unwrap()
+            .prev_migration_ids = vec![];
+
+        let issues = verify_migrations_data(&migrations_data).unwrap();
+        assert_eq!(
+            issues,
+            [
+                VerifyIssue::AsymmetricNext { id: 1, next_id: 2 },
+                VerifyIssue::MissingLink {
+                    id: 1,
+                    linked_id: 7
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn reports_asymmetric_previous_link() {
+        let mut migrations_data = migrations_data("reports_asymmetric_previous_link");
+        migrations_data
+            .migration_by_id_mut(1)
+            .unwrap()
+            .next_migration_ids = vec![];
+
+        let issues = verify_migrations_data(&migrations_data).unwrap();
+        assert_eq!(
+            issues,
+            [
+                VerifyIssue::AsymmetricPrev { id: 2, prev_id: 1 },
+                VerifyIssue::Unreachable(2),
+            ]
+        );
+    }
+
+    #[test]
+    fn reports_cycles_and_duplicates() {
+        let mut migrations_data = migrations_data("reports_cycles_and_duplicates");
+        migrations_data
+            .migration_by_id_mut(1)
+            .unwrap()
+            .prev_migration_ids = vec![0, 2];
+        migrations_data
+            .migration_by_id_mut(2)
+            .unwrap()
+            .next_migration_ids = vec![1];
+        let duplicate = migrations_data.migration_by_id(2).unwrap().clone();
+        migrations_data.migrations.push(duplicate);
+
+        let issues = verify_migrations_data(&migrations_data).unwrap();
+        assert!(issues.contains(&VerifyIssue::DuplicateId(2)));
+        assert!(issues.contains(&VerifyIssue::Cycle(vec![1, 2])));
+    }
+
+    #[test]
+    fn reports_invalid_counters() {
+        let mut migrations_data = migrations_data("reports_invalid_counters");
+        migrations_data.migrations_counter = 1;
+        migrations_data.migrations_start_id = Some(2);
+        migrations_data.migrations.remove(0);
+
+        let issues = verify_migrations_data(&migrations_data).unwrap();
+        assert_eq!(
+            issues,
+            [
+                VerifyIssue::MissingInitialMigration,
+                VerifyIssue::MissingLink {
+                    id: 1,
+                    linked_id: 0
+                },
+                VerifyIssue::InvalidStartId(2),
+                VerifyIssue::Unreachable(1),
+                VerifyIssue::CounterBehind {
+                    counter: 1,
+                    max_id: 2
+                },
+            ]
+        );
+
+        migrations_data.id_format = IdFormat::Timestamp;
+        migrations_data.migrations_counter = 5;
+        let issues = verify_migrations_data(&migrations_data).unwrap();
+        assert!(issues.contains(&VerifyIssue::CounterNotFound(5)));
+        assert!(
+            !issues
+                .iter()
+                .any(|issue| matches!(issue, VerifyIssue::CounterBehind { .. }))
+        );
+    }
+
+    #[test]
+    fn reports_missing_and_stray_files() {
+        let migrations_data = migrations_data("reports_missing_and_stray_files");
+        fs::remove_file(migrations_data.directory.join("2_b_down.sql")).unwrap();
+        fs::write(migrations_data.directory.join("3_c_up.sql"), "").unwrap();
+        fs::write(migrations_data.directory.join("notes.txt"), "").unwrap();
+
+        let issues = verify_migrations_data(&migrations_data).unwrap();
+        assert_eq!(
+            issues,
+            [
+                VerifyIssue::MissingFile {
+                    id: 2,
+                    file: PathBuf::from("2_b_down.sql")
+                },
+                VerifyIssue::StrayFile(PathBuf::from("3_c_up.sql")),
+            ]
+        );
+    }
+}